@@ -1,8 +1,11 @@
 // Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::cmp::{self, Reverse};
+use std::collections::BinaryHeap;
 use std::mem;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[cfg(not(feature = "prost-codec"))]
 use kvproto::cdcpb::*;
@@ -15,23 +18,38 @@ use kvproto::cdcpb::{
     ChangeDataEvent, Event,
 };
 
-use futures::sync::mpsc::*;
 use kvproto::metapb::{Region, RegionEpoch};
 use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, AdminResponse, CmdType, Request};
+use openssl::symm::{self, Cipher};
+use protobuf::Message;
+use rand::RngCore;
 use resolved_ts::Resolver;
 use tikv::raftstore::store::util::compare_region_epoch;
 use tikv::raftstore::Error as RaftStoreError;
 use tikv::storage::mvcc::{Lock, LockType, WriteRef, WriteType};
 use tikv::storage::txn::TxnEntry;
 use tikv_util::collections::HashMap;
+use tikv_util::mpsc::{bounded, Receiver, Sender};
 use txn_types::{Key, TimeStamp};
 
+use crate::metrics::*;
 use crate::Error;
 
 static DOWNSTREAM_ID_ALLOC: AtomicUsize = AtomicUsize::new(0);
 
+/// Default bound of a single downstream's pending-event queue. Once a
+/// downstream's queue reaches this many buffered events, it is treated as a
+/// slow consumer and evicted instead of letting the queue (and the region's
+/// memory footprint) grow without limit.
+pub const CDC_SINK_CAP: usize = 1024;
+
+/// Creates a bounded sink/source pair for a `Downstream`.
+pub fn new_downstream_sink() -> (Sender<ChangeDataEvent>, Receiver<ChangeDataEvent>) {
+    bounded(CDC_SINK_CAP)
+}
+
 /// A unique identifier of a Downstream.
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 pub struct DownstreamID(usize);
 
 impl DownstreamID {
@@ -40,6 +58,260 @@ impl DownstreamID {
     }
 }
 
+/// AEAD algorithms available for per-downstream CDC payload encryption.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum EncryptionMethod {
+    Aes128Gcm,
+    Aes256Gcm,
+}
+
+impl EncryptionMethod {
+    fn cipher(self) -> Cipher {
+        match self {
+            EncryptionMethod::Aes128Gcm => Cipher::aes_128_gcm(),
+            EncryptionMethod::Aes256Gcm => Cipher::aes_256_gcm(),
+        }
+    }
+
+    /// Key length in bytes `self`'s AEAD cipher requires.
+    fn key_len(self) -> usize {
+        match self {
+            EncryptionMethod::Aes128Gcm => 16,
+            EncryptionMethod::Aes256Gcm => 32,
+        }
+    }
+}
+
+const GCM_NONCE_LEN: usize = 12;
+const GCM_TAG_LEN: usize = 16;
+
+/// Hashes a single committed row into an MMR leaf, binding it to exactly
+/// the fields a downstream would otherwise trust the transport to deliver
+/// unmodified: which transaction produced it, when it committed, and what
+/// it changed.
+fn hash_leaf(start_ts: u64, commit_ts: u64, key: &[u8], op_type: i32, value: &[u8]) -> [u8; 32] {
+    let mut buf = Vec::with_capacity(8 + 8 + key.len() + 4 + value.len());
+    buf.extend_from_slice(&start_ts.to_be_bytes());
+    buf.extend_from_slice(&commit_ts.to_be_bytes());
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(&op_type.to_be_bytes());
+    buf.extend_from_slice(value);
+    openssl::sha::sha256(&buf)
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut buf = Vec::with_capacity(64);
+    buf.extend_from_slice(left);
+    buf.extend_from_slice(right);
+    openssl::sha::sha256(&buf)
+}
+
+/// An incremental Merkle Mountain Range over the committed rows a
+/// `Delegate` has sent out, so a downstream that independently hashes the
+/// same rows can recompute the root and detect gaps or reordering without
+/// trusting the transport.
+///
+/// Only the "peaks" (roots of the MMR's complete subtrees) are kept, each
+/// tagged with its height; appending a leaf pushes a height-0 peak, then
+/// repeatedly merges the top two peaks while they share a height.
+#[derive(Clone, Default)]
+struct MerkleMountainRange {
+    peaks: Vec<(u32, [u8; 32])>,
+    leaf_count: u64,
+}
+
+impl MerkleMountainRange {
+    fn push_leaf(&mut self, leaf: [u8; 32]) {
+        self.peaks.push((0, leaf));
+        self.leaf_count += 1;
+        while self.peaks.len() >= 2 {
+            let top = self.peaks.len() - 1;
+            if self.peaks[top].0 != self.peaks[top - 1].0 {
+                break;
+            }
+            let (height, right) = self.peaks.pop().unwrap();
+            let (_, left) = self.peaks.pop().unwrap();
+            self.peaks.push((height + 1, hash_pair(&left, &right)));
+        }
+    }
+
+    /// Folds the peaks right-to-left into a single "bag of peaks" root.
+    /// `None` before any leaf has been appended.
+    fn root(&self) -> Option<[u8; 32]> {
+        let mut iter = self.peaks.iter().rev();
+        let mut acc = iter.next()?.1;
+        for (_, peak) in iter {
+            acc = hash_pair(peak, &acc);
+        }
+        Some(acc)
+    }
+
+    fn reset(&mut self) {
+        self.peaks.clear();
+        self.leaf_count = 0;
+    }
+}
+
+/// Default time a `Downstream` is allowed to make no progress before it is
+/// treated as stuck and reaped by `Delegate::on_tick`.
+pub const DEFAULT_DOWNSTREAM_LEASE: Duration = Duration::from_secs(60);
+
+/// A time-ordered queue of per-downstream deadlines, so a periodic tick can
+/// cheaply find every downstream that has made no progress within its
+/// lease. Re-arming an entry is O(log n): the stale heap entry left behind
+/// is simply skipped, on pop, by comparing against the authoritative
+/// deadline in `deadlines`, rather than trying to remove it from the heap.
+#[derive(Default)]
+struct DelayQueue {
+    deadlines: HashMap<DownstreamID, Instant>,
+    heap: BinaryHeap<Reverse<(Instant, DownstreamID)>>,
+}
+
+impl DelayQueue {
+    /// (Re-)arms `id`'s deadline, superseding any previous one.
+    fn set(&mut self, id: DownstreamID, deadline: Instant) {
+        self.deadlines.insert(id, deadline);
+        self.heap.push(Reverse((deadline, id)));
+    }
+
+    fn remove(&mut self, id: DownstreamID) {
+        self.deadlines.remove(&id);
+    }
+
+    /// Pops every id whose deadline is at or before `now`, forgetting it so
+    /// it is not reported again. Entries superseded by a later `set` (or
+    /// already removed) are dropped silently.
+    fn pop_expired(&mut self, now: Instant) -> Vec<DownstreamID> {
+        let mut expired = Vec::new();
+        while let Some(&Reverse((deadline, id))) = self.heap.peek() {
+            if deadline > now {
+                break;
+            }
+            self.heap.pop();
+            if self.deadlines.get(&id) == Some(&deadline) {
+                self.deadlines.remove(&id);
+                expired.push(id);
+            }
+        }
+        expired
+    }
+}
+
+/// A customer-supplied AEAD key registered on a `Downstream` at subscribe
+/// time, similar to S3 SSE-C. It is held only for the lifetime of the
+/// subscription and is never persisted on the `Delegate`.
+#[derive(Clone)]
+pub struct DownstreamEncryption {
+    method: EncryptionMethod,
+    key: Vec<u8>,
+}
+
+impl DownstreamEncryption {
+    /// Fails if `key`'s length doesn't match what `method` requires. The
+    /// key is client-supplied at subscribe time (like S3 SSE-C), so this
+    /// has to be checked up front: letting a wrong-length key through would
+    /// only surface as an OpenSSL failure inside `encrypt` on the first
+    /// event, which previously `expect`-panicked the whole CDC worker
+    /// thread on what is attacker-/client-controlled input.
+    pub fn new(method: EncryptionMethod, key: Vec<u8>) -> Result<DownstreamEncryption, String> {
+        if key.len() != method.key_len() {
+            return Err(format!(
+                "downstream encryption key is {} bytes, {:?} requires {}",
+                key.len(),
+                method,
+                method.key_len()
+            ));
+        }
+        Ok(DownstreamEncryption { method, key })
+    }
+
+    /// Encrypts every non-empty row value carried by `change_data` in
+    /// place. Each such value is replaced by `nonce || tag || ciphertext`,
+    /// so a decrypting downstream needs only the shared key, no other side
+    /// channel. Empty values (e.g. deletes) are left empty, not encrypted.
+    fn encrypt_change_data(&self, change_data: &mut ChangeDataEvent) {
+        for event in change_data.mut_events().iter_mut() {
+            if let Some(Event_oneof_event::Entries(entries)) = event.event.as_mut() {
+                for row in entries.mut_entries().iter_mut() {
+                    if !row.value.is_empty() {
+                        row.value = self.encrypt(&row.value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = vec![0u8; GCM_NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut tag = vec![0u8; GCM_TAG_LEN];
+        let ciphertext = symm::encrypt_aead(
+            self.method.cipher(),
+            &self.key,
+            Some(&nonce),
+            &[],
+            plaintext,
+            &mut tag,
+        )
+        // `new` already validated `self.key`'s length against `self.method`,
+        // so the only way this can fail here is a bug in that check.
+        .expect("cdc downstream AEAD encryption should not fail");
+        let mut out = Vec::with_capacity(nonce.len() + tag.len() + ciphertext.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&tag);
+        out.extend_from_slice(&ciphertext);
+        out
+    }
+
+    /// Inverse of `encrypt`. Exposed so a downstream holding the same key
+    /// can decode what it receives. `blob` comes straight off the wire, so
+    /// unlike `encrypt` (which only ever sees nonce/tag lengths it chose
+    /// itself) this has to check its length before slicing into it instead
+    /// of letting `split_at` panic on a truncated or corrupt payload.
+    pub fn decrypt(&self, blob: &[u8]) -> Result<Vec<u8>, String> {
+        if blob.len() < GCM_NONCE_LEN + GCM_TAG_LEN {
+            return Err(format!(
+                "cdc downstream ciphertext is {} bytes, need at least {}",
+                blob.len(),
+                GCM_NONCE_LEN + GCM_TAG_LEN
+            ));
+        }
+        let (nonce, rest) = blob.split_at(GCM_NONCE_LEN);
+        let (tag, ciphertext) = rest.split_at(GCM_TAG_LEN);
+        symm::decrypt_aead(self.method.cipher(), &self.key, Some(nonce), &[], ciphertext, tag)
+            .map_err(|e| format!("cdc downstream AEAD decryption failed: {}", e))
+    }
+}
+
+/// An optional subscription filter restricting which rows a `Downstream`
+/// receives: a set of `[start_key, end_key)` ranges and/or a CF allow-list.
+/// An empty range list matches every key; a `None` CF allow-list matches
+/// every CF. A `Downstream` with no filter at all receives every row in
+/// the region, as before this existed.
+#[derive(Clone, Default)]
+pub struct DownstreamFilter {
+    key_ranges: Vec<(Vec<u8>, Vec<u8>)>,
+    cfs: Option<Vec<String>>,
+}
+
+impl DownstreamFilter {
+    pub fn new(key_ranges: Vec<(Vec<u8>, Vec<u8>)>, cfs: Option<Vec<String>>) -> DownstreamFilter {
+        DownstreamFilter { key_ranges, cfs }
+    }
+
+    fn matches(&self, key: &[u8], cf: &str) -> bool {
+        if let Some(cfs) = &self.cfs {
+            if !cfs.iter().any(|c| c == cf) {
+                return false;
+            }
+        }
+        self.key_ranges.is_empty()
+            || self.key_ranges.iter().any(|(start, end)| {
+                key >= start.as_slice() && (end.is_empty() || key < end.as_slice())
+            })
+    }
+}
+
 #[derive(Clone)]
 pub struct Downstream {
     // TODO: include cdc request.
@@ -48,30 +320,145 @@ pub struct Downstream {
     // The IP address of downstream.
     peer: String,
     region_epoch: RegionEpoch,
-    sink: UnboundedSender<ChangeDataEvent>,
+    sink: Sender<ChangeDataEvent>,
+    encryption: Option<DownstreamEncryption>,
+    filter: Option<DownstreamFilter>,
+    /// How long this downstream may go without progress before
+    /// `Delegate::on_tick` reaps it as stuck.
+    lease: Duration,
+    /// A Merkle Mountain Range over the committed rows actually sent to
+    /// *this* downstream, kept per-downstream (rather than delegate-wide)
+    /// because a filtered downstream only ever sees a subset of the
+    /// region's committed rows: a shared accumulator would hash rows the
+    /// downstream never received, so it could never recompute the root.
+    accumulator: MerkleMountainRange,
 }
 
 impl Downstream {
     /// Create a Downsteam.
     ///
     /// peer is the address of the downstream.
-    /// sink sends data to the downstream.
+    /// sink sends data to the downstream. It must be bounded (see
+    /// `new_downstream_sink`) so that a stalled downstream cannot grow its
+    /// queue without limit.
     pub fn new(
         peer: String,
         region_epoch: RegionEpoch,
-        sink: UnboundedSender<ChangeDataEvent>,
+        sink: Sender<ChangeDataEvent>,
     ) -> Downstream {
         Downstream {
             id: DownstreamID::new(),
             peer,
             sink,
             region_epoch,
+            encryption: None,
+            filter: None,
+            lease: DEFAULT_DOWNSTREAM_LEASE,
+            accumulator: MerkleMountainRange::default(),
         }
     }
 
-    fn sink(&self, change_data: ChangeDataEvent) {
-        if self.sink.unbounded_send(change_data).is_err() {
-            error!("send event failed"; "downstream" => %self.peer);
+    /// Returns the current accumulator root and number of committed rows
+    /// hashed into it so far, over the rows actually sent to this
+    /// downstream. A downstream that independently hashes every committed
+    /// row it receives (same `hash_leaf` construction) can recompute this
+    /// root; a mismatch means it missed or misordered a row.
+    pub fn accumulator_state(&self) -> (Option<[u8; 32]>, u64) {
+        (self.accumulator.root(), self.accumulator.leaf_count)
+    }
+
+    /// Hashes a committed row just sent to this downstream into its
+    /// accumulator. Only rows that actually passed this downstream's
+    /// filter (or no filter at all) may be recorded here.
+    fn record_sent_row(&mut self, start_ts: u64, commit_ts: u64, key: &[u8], op_type: i32, value: &[u8]) {
+        self.accumulator
+            .push_leaf(hash_leaf(start_ts, commit_ts, key, op_type, value));
+    }
+
+    /// Registers an AEAD key so everything sent to this downstream from now
+    /// on is encrypted. An un-keyed downstream keeps receiving plaintext,
+    /// for backward compatibility.
+    pub fn with_encryption(mut self, encryption: DownstreamEncryption) -> Downstream {
+        self.encryption = Some(encryption);
+        self
+    }
+
+    /// Overrides how long this downstream may go without progress before
+    /// being reaped as stuck. Defaults to `DEFAULT_DOWNSTREAM_LEASE`.
+    pub fn with_lease(mut self, lease: Duration) -> Downstream {
+        self.lease = lease;
+        self
+    }
+
+    /// Restricts this downstream to rows within `filter`'s key ranges and
+    /// CFs, so a client replicating only a table or index prefix is not
+    /// flooded with unrelated rows. An unfiltered downstream (the default)
+    /// keeps receiving every row.
+    pub fn with_filter(mut self, filter: DownstreamFilter) -> Downstream {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Sends `change_data` to the downstream.
+    ///
+    /// Returns `false` if the downstream's queue is saturated, in which
+    /// case the caller should evict this downstream as a slow consumer
+    /// instead of letting events pile up behind it.
+    #[must_use]
+    fn sink(&self, mut change_data: ChangeDataEvent) -> bool {
+        if let Some(encryption) = &self.encryption {
+            encryption.encrypt_change_data(&mut change_data);
+        }
+        if self.sink.try_send(change_data).is_err() {
+            warn!("cdc downstream is congested, evicting as a slow consumer";
+                "downstream" => %self.peer);
+            return false;
+        }
+        true
+    }
+}
+
+/// One leg of a multi-region subscription request: which region a client
+/// wants events from, and the region epoch it last observed.
+pub struct BatchSubscription {
+    pub region_id: u64,
+    pub region_epoch: RegionEpoch,
+}
+
+/// Subscribes one client connection, sharing a single `sink`, against a
+/// batch of regions in one call instead of opening a `Downstream` (and
+/// channel) per region -- the same relationship batch key/range requests
+/// have to per-key requests in other KV systems. Every `ChangeDataEvent` a
+/// matching delegate emits already carries its own `region_id`, so the
+/// client demultiplexes events for different regions off the shared sink
+/// without any extra bookkeeping here.
+///
+/// `delegates` resolves each requested region to its `Delegate` (e.g. an
+/// endpoint's region table). A region missing from it, or whose epoch
+/// fails `Delegate::subscribe`'s check, only fails that region's leg: an
+/// `Error` event tagged with that region's id is pushed onto `sink`, and
+/// the rest of the batch still subscribes normally.
+pub fn subscribe_regions(
+    delegates: &mut HashMap<u64, Delegate>,
+    requests: Vec<BatchSubscription>,
+    peer: String,
+    sink: Sender<ChangeDataEvent>,
+) {
+    for BatchSubscription {
+        region_id,
+        region_epoch,
+    } in requests
+    {
+        match delegates.get_mut(&region_id) {
+            Some(delegate) => {
+                let downstream = Downstream::new(peer.clone(), region_epoch, sink.clone());
+                delegate.subscribe(downstream);
+            }
+            None => {
+                let err = Error::Request(RaftStoreError::RegionNotFound(region_id).into());
+                let change_data = build_error_event(region_id, err);
+                let _ = sink.try_send(change_data);
+            }
         }
     }
 }
@@ -97,8 +484,37 @@ pub struct Delegate {
     pending: Option<Pending>,
     enabled: Arc<AtomicBool>,
     failed: bool,
+    watchdog: DelayQueue,
+    last_sent_resolved_ts: Option<u64>,
+    pending_resolved_ts: Option<u64>,
+    last_resolved_ts_sent_at: Option<Instant>,
+    min_resolved_ts_interval: Duration,
+    max_resolved_ts_interval: Duration,
+    applied_index: u64,
+    replica_read_ceiling: Option<ReplicaReadCeiling>,
+}
+
+/// Bounds resolved-ts on a replica that is not the raft leader. Recorded
+/// from a `ReadIndexRequest` issued (by the caller, through the raftstore
+/// router) before the delegate is marked ready: `safe_ts` is the leader's
+/// `max_ts` as of `read_index`, and no change the leader hadn't yet
+/// revealed at that point can have a `commit_ts` below it. The ceiling is
+/// lifted once the replica has applied through `read_index`, at which
+/// point its own resolver is caught up with everything the leader could
+/// see and is safe to trust unbounded.
+struct ReplicaReadCeiling {
+    read_index: u64,
+    safe_ts: u64,
 }
 
+/// Suppresses a new resolved-ts broadcast if one was sent more recently
+/// than this, coalescing rapid advances down to their latest value.
+pub const DEFAULT_MIN_RESOLVED_TS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Forces a heartbeat resolved-ts broadcast even if the value hasn't
+/// changed, so downstreams of a quiet region still know it is alive.
+pub const DEFAULT_MAX_RESOLVED_TS_INTERVAL: Duration = Duration::from_secs(10);
+
 impl Delegate {
     /// Create a Delegate the given region.
     pub fn new(region_id: u64) -> Delegate {
@@ -110,9 +526,34 @@ impl Delegate {
             pending: Some(Pending::default()),
             enabled: Arc::new(AtomicBool::new(true)),
             failed: false,
+            watchdog: DelayQueue::default(),
+            last_sent_resolved_ts: None,
+            pending_resolved_ts: None,
+            last_resolved_ts_sent_at: None,
+            min_resolved_ts_interval: DEFAULT_MIN_RESOLVED_TS_INTERVAL,
+            max_resolved_ts_interval: DEFAULT_MAX_RESOLVED_TS_INTERVAL,
+            applied_index: 0,
+            replica_read_ceiling: None,
         }
     }
 
+    /// Overrides the resolved-ts coalescing pacing. Defaults to
+    /// `DEFAULT_MIN_RESOLVED_TS_INTERVAL` / `DEFAULT_MAX_RESOLVED_TS_INTERVAL`.
+    pub fn set_resolved_ts_pacing(&mut self, min_interval: Duration, max_interval: Duration) {
+        self.min_resolved_ts_interval = min_interval;
+        self.max_resolved_ts_interval = max_interval;
+    }
+
+    /// Returns the accumulator root and leaf count for a specific
+    /// downstream's verifiable stream (see `Downstream::accumulator_state`),
+    /// or `None` if no such downstream is currently subscribed.
+    pub fn accumulator_state(&self, downstream_id: DownstreamID) -> Option<(Option<[u8; 32]>, u64)> {
+        self.downstreams
+            .iter()
+            .find(|d| d.id == downstream_id)
+            .map(Downstream::accumulator_state)
+    }
+
     /// Returns a shared flag.
     /// True if there are some active downstreams subscribe the region.
     /// False if all downstreams has unsubscribed.
@@ -131,11 +572,15 @@ impl Delegate {
             ) {
                 let err = Error::Request(e.into());
                 let change_data_error = self.error_event(err);
-                downstream.sink(change_data_error);
+                let _ = downstream.sink(change_data_error);
                 return;
             }
+            self.watchdog
+                .set(downstream.id, Instant::now() + downstream.lease);
             self.downstreams.push(downstream);
         } else {
+            self.watchdog
+                .set(downstream.id, Instant::now() + downstream.lease);
             self.pending.as_mut().unwrap().downstreams.push(downstream);
         }
     }
@@ -150,11 +595,12 @@ impl Delegate {
         downstreams.retain(|d| {
             if d.id == id {
                 if let Some(change_data_error) = change_data_error.clone() {
-                    d.sink(change_data_error);
+                    let _ = d.sink(change_data_error);
                 }
             }
             d.id != id
         });
+        self.watchdog.remove(id);
         let is_last = self.downstreams.is_empty();
         if is_last {
             self.enabled.store(false, Ordering::SeqCst);
@@ -163,29 +609,7 @@ impl Delegate {
     }
 
     fn error_event(&self, err: Error) -> ChangeDataEvent {
-        let mut change_data_event = Event::default();
-        let mut cdc_err = EventError::default();
-        let mut err = err.extract_error_header();
-        if err.has_region_not_found() {
-            let region_not_found = err.take_region_not_found();
-            cdc_err.set_region_not_found(region_not_found);
-        } else if err.has_not_leader() {
-            let not_leader = err.take_not_leader();
-            cdc_err.set_not_leader(not_leader);
-        } else if err.has_epoch_not_match() {
-            let epoch_not_match = err.take_epoch_not_match();
-            cdc_err.set_epoch_not_match(epoch_not_match);
-        } else {
-            panic!(
-                "region met unknown error region_id: {}, error: {:?}",
-                self.region_id, err
-            );
-        }
-        change_data_event.event = Some(Event_oneof_event::Error(cdc_err));
-        change_data_event.region_id = self.region_id;
-        let mut change_data = ChangeDataEvent::default();
-        change_data.mut_events().push(change_data_event);
-        change_data
+        build_error_event(self.region_id, err)
     }
 
     /// Fail the delegate
@@ -201,6 +625,15 @@ impl Delegate {
         let change_data = self.error_event(err);
         self.broadcast(change_data);
 
+        // Each downstream's accumulator only makes sense as a contiguous
+        // proof of the rows sent to it since the delegate last became
+        // ready; once the delegate has failed, downstreams will have to
+        // resubscribe and rescan, so reset every accumulator rather than
+        // let a stale root survive into the next attempt.
+        for d in &mut self.downstreams {
+            d.accumulator.reset();
+        }
+
         // Mark this delegate has failed.
         self.failed = true;
     }
@@ -209,14 +642,148 @@ impl Delegate {
         self.failed
     }
 
-    fn broadcast(&self, change_data: ChangeDataEvent) {
+    fn broadcast(&mut self, change_data: ChangeDataEvent) {
         let downstreams = if self.pending.is_some() {
             &self.pending.as_ref().unwrap().downstreams
         } else {
             &self.downstreams
         };
+        let mut congested = Vec::new();
+        let mut progressed = Vec::new();
         for d in downstreams {
-            d.sink(change_data.clone());
+            if d.sink(change_data.clone()) {
+                record_sink_metrics(self.region_id, &change_data);
+                progressed.push((d.id, d.lease));
+            } else {
+                congested.push(d.id);
+            }
+        }
+        let now = Instant::now();
+        for (id, lease) in progressed {
+            self.watchdog.set(id, now + lease);
+        }
+        // Evict only the congested downstreams; healthy peers keep receiving
+        // events uninterrupted.
+        //
+        // TODO(cdc-congestion-error): like `on_tick`'s lease-expiry path,
+        // `EventError` has no variant for "downstream too slow, evicted as a
+        // congested consumer"; until kvproto grows one this unsubscribes
+        // silently (`None`) instead of telling the downstream why. A
+        // congested downstream's own sink is presumably still full, so
+        // delivering the error is itself best-effort even once the variant
+        // exists.
+        for id in congested {
+            self.unsubscribe(id, None);
+        }
+    }
+
+    /// Sends `rows` to every downstream, honoring each downstream's
+    /// range/CF filter. A row tagged `None` is a control row (e.g. the
+    /// `Initialized` marker) and always passes through; a row tagged
+    /// `Some(cf)` is only delivered to downstreams whose filter admits
+    /// `(key, cf)`. When no downstream has a filter this degrades to a
+    /// single shared broadcast, same as unfiltered rows always worked.
+    fn broadcast_rows(&mut self, index: u64, rows: Vec<(EventRow, Option<&'static str>)>) {
+        let region_id = self.region_id;
+        let build_event = move |entries: Vec<EventRow>| -> ChangeDataEvent {
+            let mut event_entries = EventEntries::default();
+            event_entries.entries = entries.into();
+            let mut change_data_event = Event::default();
+            change_data_event.region_id = region_id;
+            change_data_event.index = index;
+            change_data_event.event = Some(Event_oneof_event::Entries(event_entries));
+            let mut change_data = ChangeDataEvent::default();
+            change_data.mut_events().push(change_data_event);
+            change_data
+        };
+        // Only rows that are genuinely committed (as opposed to a bare
+        // prewrite) are bound into a downstream's accumulator: the
+        // resolved ts a downstream verifies against only ever advances
+        // past committed rows.
+        let record_sent = |d: &mut Downstream, sent: &[EventRow]| {
+            for row in sent {
+                if row.commit_ts != 0 {
+                    d.record_sent_row(row.start_ts, row.commit_ts, &row.key, row.op_type, &row.value);
+                }
+            }
+        };
+        let downstreams = if self.pending.is_some() {
+            &mut self.pending.as_mut().unwrap().downstreams
+        } else {
+            &mut self.downstreams
+        };
+        let mut congested = Vec::new();
+        let mut progressed = Vec::new();
+        if downstreams.iter().all(|d| d.filter.is_none()) {
+            let sent: Vec<EventRow> = rows.into_iter().map(|(row, _)| row).collect();
+            let change_data = build_event(sent.clone());
+            for d in downstreams.iter_mut() {
+                if d.sink(change_data.clone()) {
+                    record_sink_metrics(region_id, &change_data);
+                    record_sent(d, &sent);
+                    progressed.push((d.id, d.lease));
+                } else {
+                    congested.push(d.id);
+                }
+            }
+        } else {
+            for d in downstreams.iter_mut() {
+                let filtered: Vec<EventRow> = rows
+                    .iter()
+                    .filter(|(row, cf)| match (cf, &d.filter) {
+                        (_, None) => true,
+                        (None, Some(_)) => true,
+                        (Some(cf), Some(filter)) => filter.matches(&row.key, cf),
+                    })
+                    .map(|(row, _)| row.clone())
+                    .collect();
+                let event = build_event(filtered.clone());
+                if d.sink(event.clone()) {
+                    record_sink_metrics(region_id, &event);
+                    record_sent(d, &filtered);
+                    progressed.push((d.id, d.lease));
+                } else {
+                    congested.push(d.id);
+                }
+            }
+        }
+        let now = Instant::now();
+        for (id, lease) in progressed {
+            self.watchdog.set(id, now + lease);
+        }
+        // See the TODO(cdc-congestion-error) note in `broadcast`: no
+        // `EventError` variant exists yet to tell an evicted downstream it
+        // was too slow rather than simply dropped.
+        for id in congested {
+            self.unsubscribe(id, None);
+        }
+    }
+
+    /// Records the read index and the leader's `max_ts` observed via a
+    /// `ReadIndexRequest` the caller issued through the raftstore router for
+    /// this region, so CDC can be served from a follower or learner replica
+    /// instead of only the leader. Call this before `on_region_ready`;
+    /// `on_min_ts` will bound resolved-ts to `safe_ts` until
+    /// `on_apply_index_advanced` reports the replica has caught up to
+    /// `read_index`, preserving the invariant that no change the leader
+    /// hadn't yet revealed at read-index time is hidden behind the reported
+    /// resolved-ts.
+    pub fn on_read_index_resolved(&mut self, read_index: u64, safe_ts: u64) {
+        self.replica_read_ceiling = Some(ReplicaReadCeiling {
+            read_index,
+            safe_ts,
+        });
+    }
+
+    /// Reports that the replica has applied through `applied_index`,
+    /// lifting any outstanding read-index ceiling on resolved-ts once it
+    /// has caught up.
+    pub fn on_apply_index_advanced(&mut self, applied_index: u64) {
+        self.applied_index = applied_index;
+        if let Some(ceiling) = &self.replica_read_ceiling {
+            if self.applied_index >= ceiling.read_index {
+                self.replica_read_ceiling = None;
+            }
         }
     }
 
@@ -236,6 +803,10 @@ impl Delegate {
             for (downstream_id, entries) in pending.scan {
                 self.on_scan(downstream_id, entries);
             }
+            // The pending backlog is now fully drained into `on_scan`.
+            CDC_PENDING_SCAN_GAUGE
+                .with_label_values(&[&self.region_id.to_string()])
+                .set(0);
             // TODO iter multi_batch once CDC observer is ready.
             // for batch in pending.multi_batch {
             //     self.on_batch(batch);
@@ -257,14 +828,94 @@ impl Delegate {
             Some(rts) => rts,
             None => return,
         };
-        info!("resolved ts updated";
-            "region_id" => self.region_id, "resolved_ts" => resolved_ts);
+        let resolved_ts = match &self.replica_read_ceiling {
+            Some(ceiling) => cmp::min(resolved_ts.into_inner(), ceiling.safe_ts),
+            None => resolved_ts.into_inner(),
+        };
+        let now = Instant::now();
+        if let Some(last_at) = self.last_resolved_ts_sent_at {
+            if now.duration_since(last_at) < self.min_resolved_ts_interval {
+                // Too soon since the last broadcast: coalesce into the
+                // latest value instead of sending now. It will go out on
+                // the next `on_min_ts` once the interval has elapsed, or
+                // sooner as a heartbeat if `on_tick` notices the region has
+                // gone quiet.
+                debug!("resolved ts coalesced";
+                    "region_id" => self.region_id, "resolved_ts" => resolved_ts);
+                self.pending_resolved_ts = Some(resolved_ts);
+                return;
+            }
+        }
+        self.send_resolved_ts(resolved_ts, now);
+    }
+
+    /// Sends a resolved-ts event and records when it was sent, so
+    /// `on_min_ts`'s coalescing and `on_tick`'s heartbeat can pace future
+    /// broadcasts off of it.
+    fn send_resolved_ts(&mut self, resolved_ts: u64, now: Instant) {
+        // TODO(cdc-mmr-proof): `kvproto::cdcpb::Event`'s `ResolvedTs` variant
+        // is just a bare timestamp; carrying each downstream's accumulator
+        // root and leaf count to it needs a new field on the wire message,
+        // which lives in the kvproto-generated protobuf this crate only
+        // consumes. Until that lands, log every downstream's proof
+        // alongside the resolved ts so it's at least observable, and
+        // expose it via `Downstream::accumulator_state` for in-process
+        // verification.
+        for d in &self.downstreams {
+            let (accumulator_root, leaf_count) = d.accumulator_state();
+            info!("resolved ts updated";
+                "region_id" => self.region_id, "downstream_id" => ?d.id,
+                "resolved_ts" => resolved_ts,
+                "accumulator_root" => ?accumulator_root, "leaf_count" => leaf_count);
+        }
         let mut change_data_event = Event::default();
         change_data_event.region_id = self.region_id;
-        change_data_event.event = Some(Event_oneof_event::ResolvedTs(resolved_ts.into_inner()));
+        change_data_event.event = Some(Event_oneof_event::ResolvedTs(resolved_ts));
         let mut change_data = ChangeDataEvent::default();
         change_data.mut_events().push(change_data_event);
         self.broadcast(change_data);
+        self.last_sent_resolved_ts = Some(resolved_ts);
+        self.pending_resolved_ts = None;
+        self.last_resolved_ts_sent_at = Some(now);
+        CDC_RESOLVED_TS_LAG_MS
+            .with_label_values(&[&self.region_id.to_string()])
+            .set(0);
+    }
+
+    /// Reaps downstreams that have made no progress within their lease, and
+    /// sends a heartbeat resolved-ts broadcast if the region has gone quiet
+    /// for longer than `max_resolved_ts_interval`. Meant to be driven by a
+    /// periodic caller (e.g. once per tick of the raftstore's own timer),
+    /// with `now` threaded in rather than read from the clock here so tests
+    /// can simulate elapsed time deterministically.
+    ///
+    /// TODO(cdc-lease-error): `EventError` (see `error_event`) has no
+    /// variant for "lease expired"; until kvproto grows one, the evicted
+    /// downstream is unsubscribed silently instead of being told why.
+    pub fn on_tick(&mut self, now: Instant) {
+        for id in self.watchdog.pop_expired(now) {
+            warn!("cdc downstream lease expired, evicting as stuck";
+                "region_id" => self.region_id, "downstream_id" => ?id);
+            self.unsubscribe(id, None);
+        }
+
+        if let Some(last_at) = self.last_resolved_ts_sent_at {
+            CDC_RESOLVED_TS_LAG_MS
+                .with_label_values(&[&self.region_id.to_string()])
+                .set(now.duration_since(last_at).as_millis() as i64);
+        }
+
+        let due = match self.last_resolved_ts_sent_at {
+            Some(last_at) => now.duration_since(last_at) >= self.max_resolved_ts_interval,
+            // Nothing sent yet: `on_min_ts` will send promptly once the
+            // region is ready, there is no stale value to heartbeat.
+            None => false,
+        };
+        if due {
+            if let Some(ts) = self.pending_resolved_ts.or(self.last_sent_resolved_ts) {
+                self.send_resolved_ts(ts, now);
+            }
+        }
     }
 
     // TODO fill on_batch when CDC observer is ready.
@@ -275,6 +926,9 @@ impl Delegate {
     pub fn on_scan(&mut self, downstream_id: DownstreamID, entries: Vec<Option<TxnEntry>>) {
         if let Some(pending) = self.pending.as_mut() {
             pending.scan.push((downstream_id, entries));
+            CDC_PENDING_SCAN_GAUGE
+                .with_label_values(&[&self.region_id.to_string()])
+                .set(pending.scan.len() as i64);
             return;
         }
         let d = if let Some(d) = self.downstreams.iter_mut().find(|d| d.id == downstream_id) {
@@ -284,7 +938,11 @@ impl Delegate {
             return;
         };
 
-        let mut rows = Vec::with_capacity(entries.len());
+        // Each row is tagged with the CF it came from so it can be checked
+        // against the downstream's filter; `None` marks a control row (the
+        // `Initialized` marker), which is always delivered regardless of
+        // the filter.
+        let mut rows: Vec<(EventRow, Option<&'static str>)> = Vec::with_capacity(entries.len());
         for entry in entries {
             match entry {
                 Some(TxnEntry::Prewrite { default, lock }) => {
@@ -294,7 +952,7 @@ impl Delegate {
                         continue;
                     }
                     decode_default(default.1, &mut row);
-                    rows.push(row);
+                    rows.push((row, Some("lock")));
                 }
                 Some(TxnEntry::Commit { default, write }) => {
                     let mut row = EventRow::default();
@@ -316,30 +974,63 @@ impl Delegate {
                         continue;
                     }
                     set_event_row_type(&mut row, EventLogType::Committed);
-                    rows.push(row);
+                    rows.push((row, Some("write")));
                 }
                 None => {
                     let mut row = EventRow::default();
 
                     // This type means scan has finised.
                     set_event_row_type(&mut row, EventLogType::Initialized);
-                    rows.push(row);
+                    rows.push((row, None));
                 }
             }
         }
 
+        let filtered: Vec<EventRow> = rows
+            .into_iter()
+            .filter(|(row, cf)| match (cf, &d.filter) {
+                (_, None) => true,
+                (None, Some(_)) => true,
+                (Some(cf), Some(filter)) => filter.matches(&row.key, cf),
+            })
+            .map(|(row, _)| row)
+            .collect();
+
+        // Only rows that actually passed the filter above (i.e. that `d`
+        // is about to receive) are bound into `d`'s own accumulator, so it
+        // stays a faithful proof of what this downstream was sent. As in
+        // `sink_data`, a row only counts once it is genuinely committed.
+        for row in &filtered {
+            if row.commit_ts != 0 {
+                d.record_sent_row(row.start_ts, row.commit_ts, &row.key, row.op_type, &row.value);
+            }
+        }
+
         let mut event_entries = EventEntries::default();
-        event_entries.entries = rows.into();
+        event_entries.entries = filtered.into();
         let mut change_data_event = Event::default();
         change_data_event.region_id = self.region_id;
         change_data_event.event = Some(Event_oneof_event::Entries(event_entries));
         let mut change_data = ChangeDataEvent::default();
         change_data.mut_events().push(change_data_event);
-        d.sink(change_data);
+        let region_id = self.region_id;
+        if d.sink(change_data.clone()) {
+            record_sink_metrics(region_id, &change_data);
+            self.watchdog.set(downstream_id, Instant::now() + d.lease);
+        } else {
+            self.unsubscribe(downstream_id, None);
+        }
     }
 
     fn sink_data(&mut self, index: u64, requests: Vec<Request>) {
         let mut rows = HashMap::default();
+        // Tracks which CF last established each row's identity ("write" or
+        // "lock"), so the final entries can be checked against a
+        // downstream's CF filter. A "default" put may create the row before
+        // its "write"/"lock" put arrives, but it only supplies the value and
+        // never sets a tag; a row with no "write"/"lock" put is tagged
+        // "default".
+        let mut row_cfs: HashMap<Vec<u8>, &'static str> = HashMap::default();
         for mut req in requests {
             // CDC cares about put requests only.
             if req.get_cmd_type() != CmdType::Put {
@@ -378,6 +1069,7 @@ impl Delegate {
                         row.key.clone(),
                     );
 
+                    row_cfs.insert(row.key.clone(), "write");
                     let r = rows.insert(row.key.clone(), row);
                     assert!(r.is_none());
                 }
@@ -402,6 +1094,7 @@ impl Delegate {
                     let resolver = self.resolver.as_mut().unwrap();
                     resolver.track_lock(row.start_ts.into(), row.key.clone());
 
+                    row_cfs.insert(row.key.clone(), "lock");
                     *occupied = row;
                 }
                 "" | "default" => {
@@ -415,40 +1108,46 @@ impl Delegate {
             }
         }
         let mut entries = Vec::with_capacity(rows.len());
-        for (_, v) in rows {
-            entries.push(v);
+        for (key, v) in rows {
+            let cf = row_cfs.get(&key).copied().unwrap_or("default");
+            entries.push((v, Some(cf)));
         }
-        let mut event_entries = EventEntries::default();
-        event_entries.entries = entries.into();
-        let mut change_data_event = Event::default();
-        change_data_event.region_id = self.region_id;
-        change_data_event.index = index;
-        change_data_event.event = Some(Event_oneof_event::Entries(event_entries));
-        let mut change_data = ChangeDataEvent::default();
-        change_data.mut_events().push(change_data_event);
-        self.broadcast(change_data);
+        // Each downstream's accumulator is updated inside `broadcast_rows`,
+        // once per downstream, over only the rows that actually pass that
+        // downstream's filter (see the comment there).
+        self.broadcast_rows(index, entries);
     }
 
     fn sink_admin(&mut self, request: AdminRequest, mut response: AdminResponse) {
-        let store_err = match request.get_cmd_type() {
-            AdminCmdType::Split => RaftStoreError::EpochNotMatch(
-                "split".to_owned(),
-                vec![
-                    response.mut_split().take_left(),
-                    response.mut_split().take_right(),
-                ],
+        let (cmd_type, store_err) = match request.get_cmd_type() {
+            AdminCmdType::Split => (
+                "split",
+                RaftStoreError::EpochNotMatch(
+                    "split".to_owned(),
+                    vec![
+                        response.mut_split().take_left(),
+                        response.mut_split().take_right(),
+                    ],
+                ),
             ),
-            AdminCmdType::BatchSplit => RaftStoreError::EpochNotMatch(
-                "batchsplit".to_owned(),
-                response.mut_splits().take_regions().into(),
+            AdminCmdType::BatchSplit => (
+                "batchsplit",
+                RaftStoreError::EpochNotMatch(
+                    "batchsplit".to_owned(),
+                    response.mut_splits().take_regions().into(),
+                ),
             ),
             AdminCmdType::PrepareMerge
             | AdminCmdType::CommitMerge
-            | AdminCmdType::RollbackMerge => {
-                RaftStoreError::EpochNotMatch("merge".to_owned(), vec![])
-            }
+            | AdminCmdType::RollbackMerge => (
+                "merge",
+                RaftStoreError::EpochNotMatch("merge".to_owned(), vec![]),
+            ),
             _ => return,
         };
+        CDC_ADMIN_TERMINATE_COUNTER
+            .with_label_values(&[&self.region_id.to_string(), cmd_type])
+            .inc();
         let err = Error::Request(store_err.into());
         self.fail(err);
     }
@@ -494,6 +1193,53 @@ fn decode_write(key: Vec<u8>, value: &[u8], row: &mut EventRow) -> bool {
     false
 }
 
+/// Builds the single-event `ChangeDataEvent` a downstream sees when its
+/// subscription failed, tagging it with `region_id` so a downstream
+/// multiplexing several regions over one sink (see `subscribe_regions`)
+/// can tell which leg of its subscription the error belongs to.
+fn build_error_event(region_id: u64, err: Error) -> ChangeDataEvent {
+    let mut change_data_event = Event::default();
+    let mut cdc_err = EventError::default();
+    let mut err = err.extract_error_header();
+    if err.has_region_not_found() {
+        let region_not_found = err.take_region_not_found();
+        cdc_err.set_region_not_found(region_not_found);
+    } else if err.has_not_leader() {
+        let not_leader = err.take_not_leader();
+        cdc_err.set_not_leader(not_leader);
+    } else if err.has_epoch_not_match() {
+        let epoch_not_match = err.take_epoch_not_match();
+        cdc_err.set_epoch_not_match(epoch_not_match);
+    } else {
+        panic!(
+            "region met unknown error region_id: {}, error: {:?}",
+            region_id, err
+        );
+    }
+    change_data_event.event = Some(Event_oneof_event::Error(cdc_err));
+    change_data_event.region_id = region_id;
+    let mut change_data = ChangeDataEvent::default();
+    change_data.mut_events().push(change_data_event);
+    change_data
+}
+
+/// Records the per-region event/byte counters for a `ChangeDataEvent` that
+/// was just handed to a downstream's sink successfully. Called after
+/// `Downstream::sink` reports success so the metrics track what was
+/// actually enqueued, not what was merely attempted. Aggregated per region
+/// only: `downstream_id` is not a label, since it is a process-monotonic
+/// counter that is never reused, which would otherwise leak a Prometheus
+/// series per subscribe/unsubscribe.
+fn record_sink_metrics(region_id: u64, change_data: &ChangeDataEvent) {
+    let region_id = region_id.to_string();
+    CDC_SINK_EVENT_COUNTER
+        .with_label_values(&[&region_id])
+        .inc_by(change_data.get_events().len() as i64);
+    CDC_SINK_BYTES_COUNTER
+        .with_label_values(&[&region_id])
+        .inc_by(change_data.compute_size() as i64);
+}
+
 fn decode_lock(key: Vec<u8>, value: &[u8], row: &mut EventRow) -> bool {
     let lock = Lock::parse(value).unwrap();
     let op_type = match lock.lock_type {
@@ -531,12 +1277,10 @@ mod tests {
     use engine::rocks::*;
     use engine_rocks::{RocksEngine, RocksSnapshot};
     use engine_traits::Snapshot;
-    use futures::{Future, Stream};
     use kvproto::errorpb::Error as ErrorHeader;
     use kvproto::metapb::Region;
     use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse, Response};
     use kvproto::raft_serverpb::RaftMessage;
-    use std::cell::Cell;
     use std::sync::Arc;
     use tikv::raftstore::router::RaftStoreRouter;
     use tikv::raftstore::store::{
@@ -546,7 +1290,6 @@ mod tests {
     use tikv::server::RaftKv;
     use tikv::storage::mvcc::test_util::*;
     use tikv::storage::mvcc::tests::*;
-    use tikv_util::mpsc::{bounded, Sender as UtilSender};
 
     // TODO add test_txn once cdc observer is ready.
     // https://github.com/overvenus/tikv/blob/447d10ae80b5b7fc58a4bef4631874a11237fdcf/components/cdc/src/delegate.rs#L615-L701
@@ -561,7 +1304,7 @@ mod tests {
         region.mut_region_epoch().set_conf_ver(2);
         let region_epoch = region.get_region_epoch().clone();
 
-        let (sink, events) = unbounded();
+        let (sink, rx) = bounded(CDC_SINK_CAP);
         let mut delegate = Delegate::new(region_id);
         delegate.subscribe(Downstream::new(String::new(), region_epoch, sink));
         let enabled = delegate.enabled();
@@ -570,16 +1313,8 @@ mod tests {
         resolver.init();
         delegate.on_region_ready(resolver, region);
 
-        let events_wrap = Cell::new(Some(events));
         let receive_error = || {
-            let (change_data, events) = events_wrap
-                .replace(None)
-                .unwrap()
-                .into_future()
-                .wait()
-                .unwrap();
-            events_wrap.set(Some(events));
-            let mut change_data = change_data.unwrap();
+            let mut change_data = rx.recv().unwrap();
             assert_eq!(change_data.events.len(), 1);
             let change_data_event = &mut change_data.events[0];
             let event = change_data_event.event.take().unwrap();
@@ -674,7 +1409,7 @@ mod tests {
         region.mut_region_epoch().set_conf_ver(2);
         let region_epoch = region.get_region_epoch().clone();
 
-        let (sink, events) = unbounded();
+        let (sink, rx) = bounded(CDC_SINK_CAP);
         let mut delegate = Delegate::new(region_id);
         let downstream = Downstream::new(String::new(), region_epoch, sink);
         let downstream_id = downstream.id;
@@ -682,16 +1417,8 @@ mod tests {
         let enabled = delegate.enabled();
         assert!(enabled.load(Ordering::SeqCst));
 
-        let events_wrap = Cell::new(Some(events));
         let check_event = |event_rows: Vec<EventRow>| {
-            let (change_data, events) = events_wrap
-                .replace(None)
-                .unwrap()
-                .into_future()
-                .wait()
-                .unwrap();
-            events_wrap.set(Some(events));
-            let mut change_data = change_data.unwrap();
+            let mut change_data = rx.recv().unwrap();
             assert_eq!(change_data.events.len(), 1);
             let change_data_event = &mut change_data.events[0];
             assert_eq!(change_data_event.region_id, region_id);
@@ -768,4 +1495,601 @@ mod tests {
         set_event_row_type(&mut row3, EventLogType::Initialized);
         check_event(vec![row1, row2, row3]);
     }
+
+    #[test]
+    fn test_congested_downstream_is_evicted() {
+        let region_id = 1;
+        let mut region = Region::default();
+        region.set_id(region_id);
+        region.mut_peers().push(Default::default());
+        let region_epoch = region.get_region_epoch().clone();
+
+        let mut delegate = Delegate::new(region_id);
+        // The slow downstream's queue can only ever hold a single event.
+        let (slow_sink, _slow_rx) = bounded(1);
+        let slow = Downstream::new(String::from("slow"), region_epoch.clone(), slow_sink);
+        let slow_id = slow.id;
+        delegate.subscribe(slow);
+        let (fast_sink, fast_rx) = bounded(CDC_SINK_CAP);
+        let fast = Downstream::new(String::from("fast"), region_epoch, fast_sink);
+        let fast_id = fast.id;
+        delegate.subscribe(fast);
+
+        let mut resolver = Resolver::new();
+        resolver.init();
+        delegate.on_region_ready(resolver, region);
+
+        // The first broadcast fills the slow downstream's one-event queue;
+        // since nothing ever drains it, the second broadcast finds it full.
+        let mut err_header = ErrorHeader::default();
+        err_header.set_not_leader(Default::default());
+        delegate.fail(Error::Request(err_header.clone()));
+        delegate.fail(Error::Request(err_header));
+
+        assert!(
+            delegate.downstreams.iter().all(|d| d.id != slow_id),
+            "slow downstream should have been evicted"
+        );
+        assert!(delegate.downstreams.iter().any(|d| d.id == fast_id));
+        // The healthy downstream is unaffected and keeps receiving events.
+        fast_rx.recv().unwrap();
+        fast_rx.recv().unwrap();
+    }
+
+    #[test]
+    fn test_downstream_encryption_round_trip() {
+        let region_epoch = RegionEpoch::default();
+        let plaintext = b"plaintext row value".to_vec();
+        let mut row = EventRow::default();
+        row.value = plaintext.clone();
+        let mut event_entries = EventEntries::default();
+        event_entries.mut_entries().push(row);
+        let mut change_data_event = Event::default();
+        change_data_event.event = Some(Event_oneof_event::Entries(event_entries));
+        let mut change_data = ChangeDataEvent::default();
+        change_data.mut_events().push(change_data_event);
+
+        let encryption =
+            DownstreamEncryption::new(EncryptionMethod::Aes256Gcm, vec![7u8; 32]).unwrap();
+        let (sink, rx) = bounded(1);
+        let downstream = Downstream::new(String::new(), region_epoch.clone(), sink)
+            .with_encryption(encryption.clone());
+        assert!(downstream.sink(change_data.clone()));
+
+        let received = rx.recv().unwrap();
+        let entries = match received.events[0].event.as_ref().unwrap() {
+            Event_oneof_event::Entries(entries) => entries,
+            _ => panic!("unknown event"),
+        };
+        let encrypted_value = &entries.entries[0].value;
+        assert_ne!(encrypted_value, &plaintext);
+        assert_eq!(encryption.decrypt(encrypted_value).unwrap(), plaintext);
+
+        // An un-keyed downstream keeps receiving plaintext, for backward
+        // compatibility.
+        let (sink, rx) = bounded(1);
+        let downstream = Downstream::new(String::new(), region_epoch, sink);
+        assert!(downstream.sink(change_data));
+        let received = rx.recv().unwrap();
+        let entries = match received.events[0].event.as_ref().unwrap() {
+            Event_oneof_event::Entries(entries) => entries,
+            _ => panic!("unknown event"),
+        };
+        assert_eq!(entries.entries[0].value, plaintext);
+    }
+
+    #[test]
+    fn test_downstream_encryption_rejects_wrong_key_length() {
+        // Aes256Gcm needs a 32-byte key; a client-supplied 16-byte key must
+        // be rejected up front instead of panicking inside `encrypt` on the
+        // first event.
+        assert!(DownstreamEncryption::new(EncryptionMethod::Aes256Gcm, vec![7u8; 16]).is_err());
+        assert!(DownstreamEncryption::new(EncryptionMethod::Aes128Gcm, vec![7u8; 16]).is_ok());
+    }
+
+    #[test]
+    fn test_downstream_encryption_decrypt_rejects_short_blob() {
+        let encryption =
+            DownstreamEncryption::new(EncryptionMethod::Aes256Gcm, vec![7u8; 32]).unwrap();
+        assert!(encryption.decrypt(b"too short").is_err());
+    }
+
+    #[test]
+    fn test_merkle_mountain_range_root_detects_tamper() {
+        let rows: Vec<_> = (0..5u64)
+            .map(|i| (i, i + 100, format!("key{}", i).into_bytes(), i as i32, vec![i as u8]))
+            .collect();
+
+        let mut honest = MerkleMountainRange::default();
+        for (start_ts, commit_ts, key, op_type, value) in &rows {
+            honest.push_leaf(hash_leaf(*start_ts, *commit_ts, key, *op_type, value));
+        }
+        let honest_root = honest.root();
+
+        // Recomputing over the exact same rows reproduces the same root.
+        let mut replay = MerkleMountainRange::default();
+        for (start_ts, commit_ts, key, op_type, value) in &rows {
+            replay.push_leaf(hash_leaf(*start_ts, *commit_ts, key, *op_type, value));
+        }
+        assert_eq!(honest_root, replay.root());
+        assert_eq!(honest.leaf_count, replay.leaf_count);
+
+        // Dropping one row changes both the leaf count and the root.
+        let mut dropped = MerkleMountainRange::default();
+        for (start_ts, commit_ts, key, op_type, value) in rows.iter().filter(|r| r.0 != 2) {
+            dropped.push_leaf(hash_leaf(*start_ts, *commit_ts, key, *op_type, value));
+        }
+        assert_ne!(honest.leaf_count, dropped.leaf_count);
+        assert_ne!(honest_root, dropped.root());
+
+        // Corrupting one row's value (as a man-in-the-middle transport
+        // might) keeps the leaf count the same but still changes the root.
+        let mut corrupted = MerkleMountainRange::default();
+        for (start_ts, commit_ts, key, op_type, value) in &rows {
+            let leaf = if *start_ts == 2 {
+                hash_leaf(*start_ts, *commit_ts, key, *op_type, b"tampered")
+            } else {
+                hash_leaf(*start_ts, *commit_ts, key, *op_type, value)
+            };
+            corrupted.push_leaf(leaf);
+        }
+        assert_eq!(honest.leaf_count, corrupted.leaf_count);
+        assert_ne!(honest_root, corrupted.root());
+    }
+
+    #[test]
+    fn test_delegate_accumulator_advances_with_committed_rows() {
+        let region_id = 1;
+        let mut region = Region::default();
+        region.set_id(region_id);
+        region.mut_peers().push(Default::default());
+        region.mut_region_epoch().set_version(2);
+        region.mut_region_epoch().set_conf_ver(2);
+        let region_epoch = region.get_region_epoch().clone();
+
+        let (sink, rx) = bounded(CDC_SINK_CAP);
+        let mut delegate = Delegate::new(region_id);
+        let downstream = Downstream::new(String::new(), region_epoch, sink);
+        let downstream_id = downstream.id;
+        delegate.subscribe(downstream);
+
+        let mut resolver = Resolver::new();
+        resolver.init();
+        delegate.on_region_ready(resolver, region);
+
+        let (root_before, count_before) = delegate.accumulator_state(downstream_id).unwrap();
+        assert!(root_before.is_none());
+        assert_eq!(count_before, 0);
+
+        let entries = vec![Some(
+            EntryBuilder {
+                key: b"a".to_vec(),
+                value: b"b".to_vec(),
+                start_ts: 1.into(),
+                commit_ts: 2.into(),
+                primary: vec![],
+                for_update_ts: 0.into(),
+            }
+            .build_commit(WriteType::Put, false),
+        )];
+        delegate.on_scan(downstream_id, entries);
+        rx.recv().unwrap();
+
+        let (root_after, count_after) = delegate.accumulator_state(downstream_id).unwrap();
+        assert!(root_after.is_some());
+        assert_eq!(count_after, 1);
+        assert_ne!(root_before, root_after);
+    }
+
+    #[test]
+    fn test_delegate_accumulator_excludes_filtered_out_rows() {
+        // A downstream with a key-range filter must only hash the rows it
+        // actually receives, so it can still recompute its own root; a
+        // shared, delegate-wide accumulator would include rows the
+        // downstream never saw and make that impossible.
+        let region_id = 1;
+        let mut region = Region::default();
+        region.set_id(region_id);
+        region.mut_peers().push(Default::default());
+        region.mut_region_epoch().set_version(2);
+        region.mut_region_epoch().set_conf_ver(2);
+        let region_epoch = region.get_region_epoch().clone();
+
+        let (unfiltered_sink, unfiltered_rx) = bounded(CDC_SINK_CAP);
+        let (filtered_sink, filtered_rx) = bounded(CDC_SINK_CAP);
+        let mut delegate = Delegate::new(region_id);
+        let unfiltered = Downstream::new(String::new(), region_epoch.clone(), unfiltered_sink);
+        let unfiltered_id = unfiltered.id;
+        delegate.subscribe(unfiltered);
+        let filter = DownstreamFilter::new(vec![(b"b".to_vec(), b"z".to_vec())], None);
+        let filtered = Downstream::new(String::new(), region_epoch, filtered_sink).with_filter(filter);
+        let filtered_id = filtered.id;
+        delegate.subscribe(filtered);
+
+        let mut resolver = Resolver::new();
+        resolver.init();
+        delegate.on_region_ready(resolver, region);
+
+        let entries = || {
+            vec![
+                Some(
+                    EntryBuilder {
+                        key: b"a".to_vec(),
+                        value: b"v1".to_vec(),
+                        start_ts: 1.into(),
+                        commit_ts: 2.into(),
+                        primary: vec![],
+                        for_update_ts: 0.into(),
+                    }
+                    .build_commit(WriteType::Put, false),
+                ),
+                Some(
+                    EntryBuilder {
+                        key: b"b".to_vec(),
+                        value: b"v2".to_vec(),
+                        start_ts: 3.into(),
+                        commit_ts: 4.into(),
+                        primary: vec![],
+                        for_update_ts: 0.into(),
+                    }
+                    .build_commit(WriteType::Put, false),
+                ),
+            ]
+        };
+        delegate.on_scan(unfiltered_id, entries());
+        unfiltered_rx.recv().unwrap();
+        delegate.on_scan(filtered_id, entries());
+        filtered_rx.recv().unwrap();
+
+        // The unfiltered downstream saw both committed rows; the filtered
+        // downstream only saw "b", so its own accumulator must only have
+        // hashed that one row, not both.
+        let (unfiltered_root, unfiltered_count) = delegate.accumulator_state(unfiltered_id).unwrap();
+        let (filtered_root, filtered_count) = delegate.accumulator_state(filtered_id).unwrap();
+        assert_eq!(unfiltered_count, 2);
+        assert_eq!(filtered_count, 1);
+        assert_ne!(unfiltered_root, filtered_root);
+    }
+
+    #[test]
+    fn test_downstream_filter_excludes_out_of_range_keys() {
+        let region_id = 1;
+        let mut region = Region::default();
+        region.set_id(region_id);
+        region.mut_peers().push(Default::default());
+        region.mut_region_epoch().set_version(2);
+        region.mut_region_epoch().set_conf_ver(2);
+        let region_epoch = region.get_region_epoch().clone();
+
+        let (sink, rx) = bounded(CDC_SINK_CAP);
+        let mut delegate = Delegate::new(region_id);
+        let filter = DownstreamFilter::new(vec![(b"a".to_vec(), b"b".to_vec())], None);
+        let downstream = Downstream::new(String::new(), region_epoch, sink).with_filter(filter);
+        let downstream_id = downstream.id;
+        delegate.subscribe(downstream);
+
+        let mut resolver = Resolver::new();
+        resolver.init();
+        delegate.on_region_ready(resolver, region);
+
+        let entries = vec![
+            Some(
+                EntryBuilder {
+                    key: b"a".to_vec(),
+                    value: b"in-range".to_vec(),
+                    start_ts: 1.into(),
+                    commit_ts: 2.into(),
+                    primary: vec![],
+                    for_update_ts: 0.into(),
+                }
+                .build_commit(WriteType::Put, false),
+            ),
+            Some(
+                EntryBuilder {
+                    key: b"c".to_vec(),
+                    value: b"out-of-range".to_vec(),
+                    start_ts: 1.into(),
+                    commit_ts: 2.into(),
+                    primary: vec![],
+                    for_update_ts: 0.into(),
+                }
+                .build_commit(WriteType::Put, false),
+            ),
+            None,
+        ];
+        delegate.on_scan(downstream_id, entries);
+
+        let mut change_data = rx.recv().unwrap();
+        let change_data_event = &mut change_data.events[0];
+        let event = change_data_event.event.take().unwrap();
+        let rows = match event {
+            Event_oneof_event::Entries(entries) => entries.entries,
+            _ => panic!("unknown event"),
+        };
+        // The out-of-range row is dropped; the in-range row and the
+        // unconditional `Initialized` control row still arrive.
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].key, b"a");
+        assert_eq!(rows[1].get_type(), EventLogType::Initialized);
+
+        // Resolved ts still advances and reaches the filtered downstream
+        // unconditionally; it is a control event, not a row.
+        delegate.on_min_ts(10.into());
+        let mut change_data = rx.recv().unwrap();
+        let change_data_event = &mut change_data.events[0];
+        match change_data_event.event.take().unwrap() {
+            Event_oneof_event::ResolvedTs(ts) => assert_eq!(ts, 10),
+            _ => panic!("unknown event"),
+        }
+    }
+
+    #[test]
+    fn test_idle_downstream_is_reaped_active_survives() {
+        let region_id = 1;
+        let mut region = Region::default();
+        region.set_id(region_id);
+        region.mut_peers().push(Default::default());
+        region.mut_region_epoch().set_version(2);
+        region.mut_region_epoch().set_conf_ver(2);
+        let region_epoch = region.get_region_epoch().clone();
+
+        let mut delegate = Delegate::new(region_id);
+
+        // A lease of zero means the idle downstream's deadline is already
+        // due the instant it is subscribed.
+        let (idle_sink, _idle_rx) = bounded(CDC_SINK_CAP);
+        let idle = Downstream::new(String::from("idle"), region_epoch.clone(), idle_sink)
+            .with_lease(Duration::from_secs(0));
+        let idle_id = idle.id;
+        delegate.subscribe(idle);
+
+        // A long lease keeps the active downstream's deadline far in the
+        // future regardless of how long the test takes to run.
+        let (active_sink, active_rx) = bounded(CDC_SINK_CAP);
+        let active = Downstream::new(String::from("active"), region_epoch, active_sink)
+            .with_lease(Duration::from_secs(3600));
+        let active_id = active.id;
+        delegate.subscribe(active);
+
+        let mut resolver = Resolver::new();
+        resolver.init();
+        delegate.on_region_ready(resolver, region);
+
+        // The active downstream makes progress, re-arming its (long) lease;
+        // the idle one never does.
+        delegate.on_scan(active_id, vec![None]);
+        active_rx.recv().unwrap();
+
+        delegate.on_tick(Instant::now());
+
+        assert!(
+            delegate.downstreams.iter().all(|d| d.id != idle_id),
+            "idle downstream should have been reaped"
+        );
+        assert!(delegate.downstreams.iter().any(|d| d.id == active_id));
+    }
+
+    #[test]
+    fn test_resolved_ts_rapid_advances_coalesce() {
+        let region_id = 1;
+        let mut region = Region::default();
+        region.set_id(region_id);
+        region.mut_peers().push(Default::default());
+        region.mut_region_epoch().set_version(2);
+        region.mut_region_epoch().set_conf_ver(2);
+        let region_epoch = region.get_region_epoch().clone();
+
+        let (sink, rx) = bounded(CDC_SINK_CAP);
+        let mut delegate = Delegate::new(region_id);
+        delegate.set_resolved_ts_pacing(Duration::from_secs(3600), Duration::from_secs(3600));
+        delegate.subscribe(Downstream::new(String::new(), region_epoch, sink));
+
+        let mut resolver = Resolver::new();
+        resolver.init();
+        delegate.on_region_ready(resolver, region);
+
+        // A freshly ready region emits its first resolved ts promptly, with
+        // no prior broadcast to coalesce against.
+        delegate.on_min_ts(1.into());
+        let mut change_data = rx.recv().unwrap();
+        match change_data.events[0].event.take().unwrap() {
+            Event_oneof_event::ResolvedTs(ts) => assert_eq!(ts, 1),
+            _ => panic!("unknown event"),
+        }
+
+        // Rapid subsequent advances land within `min_resolved_ts_interval`
+        // of the first broadcast and are coalesced: no further events are
+        // sent, but the latest value is retained.
+        delegate.on_min_ts(2.into());
+        delegate.on_min_ts(3.into());
+        assert!(rx.try_recv().is_err());
+        assert_eq!(delegate.pending_resolved_ts, Some(3));
+    }
+
+    #[test]
+    fn test_resolved_ts_heartbeats_when_quiet() {
+        let region_id = 1;
+        let mut region = Region::default();
+        region.set_id(region_id);
+        region.mut_peers().push(Default::default());
+        region.mut_region_epoch().set_version(2);
+        region.mut_region_epoch().set_conf_ver(2);
+        let region_epoch = region.get_region_epoch().clone();
+
+        let (sink, rx) = bounded(CDC_SINK_CAP);
+        let mut delegate = Delegate::new(region_id);
+        delegate.set_resolved_ts_pacing(Duration::from_secs(3600), Duration::from_secs(0));
+        delegate.subscribe(Downstream::new(String::new(), region_epoch, sink));
+
+        let mut resolver = Resolver::new();
+        resolver.init();
+        delegate.on_region_ready(resolver, region);
+
+        delegate.on_min_ts(5.into());
+        let mut change_data = rx.recv().unwrap();
+        match change_data.events[0].event.take().unwrap() {
+            Event_oneof_event::ResolvedTs(ts) => assert_eq!(ts, 5),
+            _ => panic!("unknown event"),
+        }
+
+        // The region goes quiet: no new resolved ts arrives, but the tick
+        // heartbeat re-sends the last value once `max_resolved_ts_interval`
+        // (here, zero) has elapsed.
+        delegate.on_tick(Instant::now());
+        let mut change_data = rx.recv().unwrap();
+        match change_data.events[0].event.take().unwrap() {
+            Event_oneof_event::ResolvedTs(ts) => assert_eq!(ts, 5),
+            _ => panic!("unknown event"),
+        }
+    }
+
+    #[test]
+    fn test_replica_read_ceiling_bounds_resolved_ts_until_applied() {
+        let region_id = 1;
+        let mut region = Region::default();
+        region.set_id(region_id);
+        region.mut_peers().push(Default::default());
+        region.mut_region_epoch().set_version(2);
+        region.mut_region_epoch().set_conf_ver(2);
+        let region_epoch = region.get_region_epoch().clone();
+
+        let (sink, rx) = bounded(CDC_SINK_CAP);
+        let mut delegate = Delegate::new(region_id);
+        delegate.set_resolved_ts_pacing(Duration::from_secs(0), Duration::from_secs(3600));
+        delegate.subscribe(Downstream::new(String::new(), region_epoch, sink));
+
+        // A replica-read subscription records the read index and the
+        // leader's max_ts before the delegate is marked ready.
+        delegate.on_read_index_resolved(100, 5);
+
+        let mut resolver = Resolver::new();
+        resolver.init();
+        delegate.on_region_ready(resolver, region);
+
+        // Even though the resolver itself could advance further, the
+        // reported resolved-ts is capped at the read-index safe_ts.
+        delegate.on_min_ts(10.into());
+        let mut change_data = rx.recv().unwrap();
+        match change_data.events[0].event.take().unwrap() {
+            Event_oneof_event::ResolvedTs(ts) => assert_eq!(ts, 5),
+            _ => panic!("unknown event"),
+        }
+
+        // Applying past the read index lifts the ceiling; resolved-ts can
+        // now advance past the leader's max_ts as of the read index.
+        delegate.on_apply_index_advanced(100);
+        delegate.on_min_ts(10.into());
+        let mut change_data = rx.recv().unwrap();
+        match change_data.events[0].event.take().unwrap() {
+            Event_oneof_event::ResolvedTs(ts) => assert_eq!(ts, 10),
+            _ => panic!("unknown event"),
+        }
+    }
+
+    #[test]
+    fn test_pending_scan_gauge_tracks_backlog() {
+        let region_id = 12345;
+        let mut region = Region::default();
+        region.set_id(region_id);
+        region.mut_peers().push(Default::default());
+        region.mut_region_epoch().set_version(2);
+        region.mut_region_epoch().set_conf_ver(2);
+        let region_epoch = region.get_region_epoch().clone();
+
+        let (sink, _rx) = bounded(CDC_SINK_CAP);
+        let mut delegate = Delegate::new(region_id);
+        let downstream = Downstream::new(String::new(), region_epoch, sink);
+        let downstream_id = downstream.id;
+        delegate.subscribe(downstream);
+
+        let label = region_id.to_string();
+        // Scans that arrive before the resolver is ready queue up in
+        // `pending.scan`, and the gauge tracks the backlog.
+        delegate.on_scan(downstream_id, vec![None]);
+        delegate.on_scan(downstream_id, vec![None]);
+        assert_eq!(CDC_PENDING_SCAN_GAUGE.with_label_values(&[&label]).get(), 2);
+
+        let mut resolver = Resolver::new();
+        resolver.init();
+        delegate.on_region_ready(resolver, region);
+
+        // Once the region is ready, the backlog has been drained.
+        assert_eq!(CDC_PENDING_SCAN_GAUGE.with_label_values(&[&label]).get(), 0);
+    }
+
+    #[test]
+    fn test_subscribe_regions_multiplexes_over_one_sink() {
+        let mut region1 = Region::default();
+        region1.set_id(1);
+        region1.mut_peers().push(Default::default());
+        region1.mut_region_epoch().set_version(2);
+        region1.mut_region_epoch().set_conf_ver(2);
+        let epoch1 = region1.get_region_epoch().clone();
+        let mut delegate1 = Delegate::new(1);
+        let mut resolver1 = Resolver::new();
+        resolver1.init();
+        delegate1.on_region_ready(resolver1, region1);
+
+        let mut region2 = Region::default();
+        region2.set_id(2);
+        region2.mut_peers().push(Default::default());
+        region2.mut_region_epoch().set_version(5);
+        region2.mut_region_epoch().set_conf_ver(5);
+        let mut delegate2 = Delegate::new(2);
+        let mut resolver2 = Resolver::new();
+        resolver2.init();
+        delegate2.on_region_ready(resolver2, region2);
+        let mut stale_epoch2 = delegate2
+            .region
+            .as_ref()
+            .unwrap()
+            .get_region_epoch()
+            .clone();
+        stale_epoch2.set_version(1);
+
+        let mut delegates = HashMap::default();
+        delegates.insert(1, delegate1);
+        delegates.insert(2, delegate2);
+
+        let (sink, rx) = bounded(CDC_SINK_CAP);
+        subscribe_regions(
+            &mut delegates,
+            vec![
+                BatchSubscription {
+                    region_id: 1,
+                    region_epoch: epoch1,
+                },
+                BatchSubscription {
+                    region_id: 2,
+                    region_epoch: stale_epoch2,
+                },
+                BatchSubscription {
+                    region_id: 3,
+                    region_epoch: Default::default(),
+                },
+            ],
+            String::new(),
+            sink,
+        );
+
+        // Region 1 subscribed cleanly.
+        assert_eq!(delegates[&1].downstreams.len(), 1);
+        // Region 2's stale epoch only fails that leg: no downstream was
+        // added, but an error event tagged region_id=2 reached the shared
+        // sink instead of the whole batch being rejected.
+        assert_eq!(delegates[&2].downstreams.len(), 0);
+        // Region 3 isn't in `delegates` at all; same per-region failure.
+        let mut seen_regions = vec![];
+        for _ in 0..2 {
+            let mut change_data = rx.recv().unwrap();
+            let event = &mut change_data.events[0];
+            assert!(matches!(
+                event.event.as_ref().unwrap(),
+                Event_oneof_event::Error(_)
+            ));
+            seen_regions.push(event.region_id);
+        }
+        seen_regions.sort();
+        assert_eq!(seen_regions, vec![2, 3]);
+    }
 }
@@ -13,6 +13,7 @@ extern crate failure;
 mod delegate;
 mod endpoint;
 mod errors;
+mod metrics;
 mod observer;
 mod service;
 
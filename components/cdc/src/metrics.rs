@@ -0,0 +1,58 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use lazy_static::lazy_static;
+use prometheus::*;
+
+lazy_static! {
+    /// Number of scan results buffered in a `Delegate`'s `Pending` queue,
+    /// i.e. `delegate.pending.scan.len()`. A region stuck here for a long
+    /// time means its resolver isn't ready yet and downstreams subscribed
+    /// to it are waiting on the initial scan rather than streaming.
+    pub static ref CDC_PENDING_SCAN_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_cdc_pending_scan",
+        "Bucket of pending scan results per region waiting on resolver readiness",
+        &["region_id"]
+    )
+    .unwrap();
+
+    /// Milliseconds between now and a region's last broadcast resolved ts.
+    /// A region whose lag keeps growing has a resolver that isn't
+    /// advancing, which is otherwise invisible until a downstream times out.
+    pub static ref CDC_RESOLVED_TS_LAG_MS: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_cdc_resolved_ts_lag_ms",
+        "Milliseconds since a region's last resolved-ts broadcast",
+        &["region_id"]
+    )
+    .unwrap();
+
+    /// Change-data events emitted to a downstream's sink, aggregated per
+    /// region. Downstream id is deliberately not a label: it comes from a
+    /// process-monotonic counter, so every subscribe/unsubscribe would mint
+    /// a new, never-reclaimed series and leak label cardinality on a
+    /// long-running node.
+    pub static ref CDC_SINK_EVENT_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_cdc_sink_event_total",
+        "Total number of change-data events pushed into a downstream's sink",
+        &["region_id"]
+    )
+    .unwrap();
+
+    /// Serialized bytes pushed into a downstream's sink, split the same way
+    /// as `CDC_SINK_EVENT_COUNTER`.
+    pub static ref CDC_SINK_BYTES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_cdc_sink_bytes_total",
+        "Total number of serialized bytes pushed into a downstream's sink",
+        &["region_id"]
+    )
+    .unwrap();
+
+    /// Terminations raised by `sink_admin`, split by region and the admin
+    /// command that caused them (split/batch-split/merge all surface as
+    /// `EpochNotMatch`).
+    pub static ref CDC_ADMIN_TERMINATE_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_cdc_admin_terminate_total",
+        "Total number of delegates failed by an admin command (epoch-not-match, region merge)",
+        &["region_id", "cmd_type"]
+    )
+    .unwrap();
+}
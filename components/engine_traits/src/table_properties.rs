@@ -1,5 +1,7 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::collections::HashMap;
+
 use crate::errors::Result;
 use crate::range::Range;
 use crate::CFHandleExt;
@@ -23,6 +25,94 @@ pub trait TablePropertiesExt: CFHandleExt {
         let range = Range::new(start_key, end_key);
         Ok(self.get_properties_of_tables_in_range(cf, &[range])?)
     }
+
+    /// Convenience wrapper around `get_range_properties_cf` for callers
+    /// (MVCC GC, range statistics, hot-region detection) that only want one
+    /// value a registered collector aggregated over the range, not the rest
+    /// of `TablePropertiesCollection`.
+    fn get_user_collected_properties_cf(
+        &self,
+        cfname: &str,
+        start_key: &[u8],
+        end_key: &[u8],
+        index: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let props = self.get_range_properties_cf(cfname, start_key, end_key)?;
+        Ok(props
+            .user_collected_properties()
+            .get(index)
+            .map(|v| v.to_vec()))
+    }
+
+    /// Registers a user-defined properties collector factory under `name`
+    /// for `cf`, so every SST built for that column family from now on also
+    /// runs the factory's collector. `name` is the key its aggregate is
+    /// filed under in `UserCollectedProperties`, so it must stay stable
+    /// across restarts.
+    ///
+    /// Defaults to a no-op so existing implementors keep compiling without
+    /// wiring in user-defined collectors; `user_collected_properties` then
+    /// simply has nothing to report for `name`.
+    fn register_properties_collector(
+        &self,
+        _name: &str,
+        _cf: &Self::CFHandle,
+        _factory: Box<dyn TablePropertiesCollectorFactory>,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub trait TablePropertiesCollection {
+    /// User-collected properties keyed by the `name` passed to
+    /// `register_properties_collector`, aggregated over every SST this
+    /// collection covers.
+    ///
+    /// Defaults to an always-empty view, for implementors that don't fill
+    /// in user-defined collectors.
+    fn user_collected_properties(&self) -> &dyn UserCollectedProperties {
+        &EMPTY_USER_COLLECTED_PROPERTIES
+    }
 }
 
-pub trait TablePropertiesCollection {}
+/// Default `UserCollectedProperties` for implementors that don't register
+/// any collector: reports nothing for every key.
+struct EmptyUserCollectedProperties;
+
+impl UserCollectedProperties for EmptyUserCollectedProperties {
+    fn get(&self, _index: &[u8]) -> Option<&[u8]> {
+        None
+    }
+}
+
+static EMPTY_USER_COLLECTED_PROPERTIES: EmptyUserCollectedProperties =
+    EmptyUserCollectedProperties;
+
+/// Read-only view of a collector's aggregate once its SST is sealed.
+pub trait UserCollectedProperties {
+    fn get(&self, index: &[u8]) -> Option<&[u8]>;
+}
+
+/// Classification handed to a collector's `add` call for each entry, so a
+/// collector can e.g. skip tombstones when counting live keys.
+pub enum EntryType {
+    Put,
+    Delete,
+    Other,
+}
+
+/// Observes every entry written while an SST is being built and folds them
+/// into an aggregate, for statistics (MVCC version counts, tombstone
+/// ratios, key/row counts) that would otherwise require a full scan.
+pub trait TablePropertiesCollector: Send {
+    fn add(&mut self, key: &[u8], value: &[u8], entry_type: EntryType, seq: u64);
+
+    fn finish(&mut self) -> HashMap<Vec<u8>, Vec<u8>>;
+}
+
+/// Constructs a fresh `TablePropertiesCollector` for each SST as it starts
+/// being built, so collector instances never need to be reset or shared
+/// across files.
+pub trait TablePropertiesCollectorFactory: Send + Sync {
+    fn create_table_properties_collector(&self, cf: &str) -> Box<dyn TablePropertiesCollector>;
+}
@@ -84,6 +84,89 @@ fn json_modify_validator(expr: &tipb::Expr) -> Result<()> {
     Ok(())
 }
 
+#[rpn_fn(raw_varg, min_args = 2, extra_validator = json_modify_validator)]
+#[inline]
+fn json_array_append(args: &[ScalarValueRef]) -> Result<Option<Json>> {
+    assert!(args.len() >= 2);
+    let base: &Option<Json> = args[0].as_ref();
+    let mut base = base.as_ref().map_or(Json::None, |json| json.to_owned());
+
+    for chunk in args[1..].chunks(2) {
+        let path: &Option<Bytes> = chunk[0].as_ref();
+        let path_expr = try_opt!(parse_json_path(path));
+
+        let value: &Option<Json> = chunk[1].as_ref();
+        let value = value.as_ref().map_or(Json::None, |json| json.to_owned());
+
+        // A path that does not identify a value is a no-op for that pair.
+        let new_value = match base.extract(&[path_expr.clone()]) {
+            None => continue,
+            Some(Json::Array(mut items)) => {
+                items.push(value);
+                Json::Array(items)
+            }
+            Some(other) => Json::Array(vec![other, value]),
+        };
+        base.modify(&[path_expr], vec![new_value], ModifyType::Set)?;
+    }
+
+    Ok(Some(base))
+}
+
+#[rpn_fn(raw_varg, min_args = 2, extra_validator = json_modify_validator)]
+#[inline]
+fn json_array_insert(args: &[ScalarValueRef]) -> Result<Option<Json>> {
+    assert!(args.len() >= 2);
+    let base: &Option<Json> = args[0].as_ref();
+    let mut base = base.as_ref().map_or(Json::None, |json| json.to_owned());
+
+    for chunk in args[1..].chunks(2) {
+        let path: &Option<Bytes> = chunk[0].as_ref();
+        let path_str = match path.as_ref() {
+            None => return Ok(None),
+            Some(p) => std::str::from_utf8(p)
+                .map_err(crate::codec::Error::from)?
+                .to_owned(),
+        };
+
+        let value: &Option<Json> = chunk[1].as_ref();
+        let value = value.as_ref().map_or(Json::None, |json| json.to_owned());
+
+        // The path's last leg must be an array index; anything else is a
+        // no-op for that pair.
+        let (parent_path_str, index) = match split_last_array_index(&path_str) {
+            Some(v) => v,
+            None => continue,
+        };
+        let parent_path = parse_json_path_expr(&parent_path_str)?;
+        let mut items = match base.extract(&[parent_path.clone()]) {
+            Some(Json::Array(items)) => items,
+            _ => continue,
+        };
+        if index >= items.len() {
+            items.push(value);
+        } else {
+            items.insert(index, value);
+        }
+        base.modify(&[parent_path], vec![Json::Array(items)], ModifyType::Set)?;
+    }
+
+    Ok(Some(base))
+}
+
+/// Splits a trailing array-index leg off a JSON path string, e.g.
+/// `$.a[2]` -> (`$.a`, 2). Returns `None` if the path does not end in an
+/// index leg.
+fn split_last_array_index(path: &str) -> Option<(String, usize)> {
+    let path = path.trim_end();
+    if !path.ends_with(']') {
+        return None;
+    }
+    let open = path.rfind('[')?;
+    let index: usize = path[open + 1..path.len() - 1].parse().ok()?;
+    Some((path[..open].to_owned(), index))
+}
+
 #[rpn_fn(varg)]
 #[inline]
 fn json_array(args: &[&Option<Json>]) -> Result<Option<Json>> {
@@ -158,6 +241,51 @@ pub fn json_merge(args: &[&Option<Json>]) -> Result<Option<Json>> {
         }))
 }
 
+// JSON_MERGE_PATCH implements RFC 7396 merge-patch semantics, as opposed to
+// `json_merge` above which implements MySQL's JSON_MERGE_PRESERVE semantics.
+// Arguments of json_merge_patch should not be less than 2, same as json_merge.
+#[rpn_fn(varg, min_args = 2)]
+#[inline]
+pub fn json_merge_patch(args: &[&Option<Json>]) -> Result<Option<Json>> {
+    // min_args = 2, so it's ok to call args[0]
+    let mut acc = args[0].as_ref().map(|json| json.to_owned());
+    for patch in &args[1..] {
+        acc = match patch {
+            // A NULL operand makes the whole result NULL, unless a later
+            // non-null object overrides it.
+            None => None,
+            Some(patch) => Some(match acc {
+                None => json_merge_patch_one(Json::None, patch.to_owned()),
+                Some(target) => json_merge_patch_one(target, patch.to_owned()),
+            }),
+        };
+    }
+    Ok(acc)
+}
+
+/// Merge-patches `target` with `patch` per RFC 7396: a non-object `patch`
+/// wholly replaces `target`; an object `patch` recursively merges member by
+/// member, removing members whose patch value is JSON null.
+fn json_merge_patch_one(target: Json, patch: Json) -> Json {
+    let patch_map = match patch {
+        Json::Object(patch_map) => patch_map,
+        _ => return patch,
+    };
+    let mut result = match target {
+        Json::Object(m) => m,
+        _ => BTreeMap::new(),
+    };
+    for (key, value) in patch_map {
+        if let Json::None = value {
+            result.remove(&key);
+        } else {
+            let existing = result.remove(&key).unwrap_or(Json::None);
+            result.insert(key, json_merge_patch_one(existing, value));
+        }
+    }
+    Json::Object(result)
+}
+
 #[rpn_fn]
 #[inline]
 fn json_unquote(arg: &Option<Json>) -> Result<Option<Bytes>> {
@@ -166,6 +294,89 @@ fn json_unquote(arg: &Option<Json>) -> Result<Option<Bytes>> {
     })
 }
 
+#[rpn_fn]
+#[inline]
+fn json_pretty(arg: &Option<Json>) -> Result<Option<Bytes>> {
+    arg.as_ref().map_or(Ok(None), |json_arg| {
+        let mut buf = String::new();
+        json_pretty_one(json_arg, 0, &mut buf);
+        Ok(Some(Bytes::from(buf)))
+    })
+}
+
+/// Pretty-prints `json` with 2-space indentation, one element/member per line.
+fn json_pretty_one(json: &Json, indent: usize, buf: &mut String) {
+    let pad = "  ".repeat(indent);
+    let inner_pad = "  ".repeat(indent + 1);
+    match json {
+        Json::Object(map) if map.is_empty() => buf.push_str("{}"),
+        Json::Object(map) => {
+            buf.push_str("{\n");
+            for (i, (k, v)) in map.iter().enumerate() {
+                if i > 0 {
+                    buf.push_str(",\n");
+                }
+                buf.push_str(&inner_pad);
+                push_json_quoted_string(k, buf);
+                buf.push_str(": ");
+                json_pretty_one(v, indent + 1, buf);
+            }
+            buf.push('\n');
+            buf.push_str(&pad);
+            buf.push('}');
+        }
+        Json::Array(items) if items.is_empty() => buf.push_str("[]"),
+        Json::Array(items) => {
+            buf.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    buf.push_str(",\n");
+                }
+                buf.push_str(&inner_pad);
+                json_pretty_one(item, indent + 1, buf);
+            }
+            buf.push('\n');
+            buf.push_str(&pad);
+            buf.push(']');
+        }
+        Json::String(s) => push_json_quoted_string(s, buf),
+        other => buf.push_str(&other.to_string()),
+    }
+}
+
+/// Appends `s` to `buf` as a double-quoted, properly-escaped JSON string.
+/// Shared by `json_quote` and `json_pretty_one` so that object keys, member
+/// values, and the top-level `JSON_QUOTE` result all escape the same way;
+/// unlike `format!("{:?}", s)`, this never emits Rust's `\u{..}` syntax.
+fn push_json_quoted_string(s: &str, buf: &mut String) {
+    buf.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\t' => buf.push_str("\\t"),
+            '\r' => buf.push_str("\\r"),
+            '\u{8}' => buf.push_str("\\b"),
+            '\u{c}' => buf.push_str("\\f"),
+            c if (c as u32) < 0x20 => buf.push_str(&format!("\\u{:04x}", c as u32)),
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+#[rpn_fn]
+#[inline]
+fn json_quote(arg: &Option<Bytes>) -> Result<Option<Bytes>> {
+    arg.as_ref().map_or(Ok(None), |bytes| {
+        let s = std::str::from_utf8(bytes).map_err(crate::codec::Error::from)?;
+        let mut buf = String::with_capacity(s.len() + 2);
+        push_json_quoted_string(s, &mut buf);
+        Ok(Some(Bytes::from(buf)))
+    })
+}
+
 // Args should be like `(&Option<Json> , &[&Option<Bytes>])`.
 fn json_with_paths_validator(expr: &tipb::Expr) -> Result<()> {
     assert!(expr.get_children().len() >= 2);
@@ -215,6 +426,35 @@ fn json_length(args: &[ScalarValueRef]) -> Result<Option<Int>> {
     Ok(parse_json_path_list(&args[1..])?.and_then(|path_expr_list| j.json_length(&path_expr_list)))
 }
 
+#[rpn_fn(raw_varg,min_args= 1, max_args = 2, extra_validator = json_with_path_validator)]
+#[inline]
+fn json_keys(args: &[ScalarValueRef]) -> Result<Option<Json>> {
+    assert!(!args.is_empty() && args.len() <= 2);
+    let j: &Option<Json> = args[0].as_ref();
+    let j = match j.as_ref() {
+        None => return Ok(None),
+        Some(j) => j.to_owned(),
+    };
+
+    let target = if args.len() == 2 {
+        let path: &Option<Bytes> = args[1].as_ref();
+        let path_expr = try_opt!(parse_json_path(path));
+        match j.extract(&[path_expr]) {
+            Some(v) => v,
+            None => return Ok(None),
+        }
+    } else {
+        j
+    };
+
+    match target {
+        Json::Object(map) => Ok(Some(Json::Array(
+            map.keys().map(|k| Json::String(k.to_owned())).collect(),
+        ))),
+        _ => Ok(None),
+    }
+}
+
 #[rpn_fn(raw_varg, min_args = 2, extra_validator = json_with_paths_validator)]
 #[inline]
 fn json_remove(args: &[ScalarValueRef]) -> Result<Option<Json>> {
@@ -231,6 +471,314 @@ fn json_remove(args: &[ScalarValueRef]) -> Result<Option<Json>> {
     Ok(Some(j))
 }
 
+// Args should be like `(&Option<Json>, &Option<Json>[, &Option<Bytes>])`.
+fn json_contains_validator(expr: &tipb::Expr) -> Result<()> {
+    let children = expr.get_children();
+    assert!(children.len() == 2 || children.len() == 3);
+    super::function::validate_expr_return_type(&children[0], EvalType::Json)?;
+    super::function::validate_expr_return_type(&children[1], EvalType::Json)?;
+    if children.len() == 3 {
+        super::function::validate_expr_return_type(&children[2], EvalType::Bytes)?;
+    }
+    Ok(())
+}
+
+#[rpn_fn(raw_varg, min_args = 2, max_args = 3, extra_validator = json_contains_validator)]
+#[inline]
+fn json_contains(args: &[ScalarValueRef]) -> Result<Option<Int>> {
+    assert!(args.len() == 2 || args.len() == 3);
+    let target: &Option<Json> = args[0].as_ref();
+    let target = match target.as_ref() {
+        None => return Ok(None),
+        Some(j) => j,
+    };
+    let candidate: &Option<Json> = args[1].as_ref();
+    let candidate = match candidate.as_ref() {
+        None => return Ok(None),
+        Some(j) => j,
+    };
+
+    let target = if args.len() == 3 {
+        let path: &Option<Bytes> = args[2].as_ref();
+        let path_expr = try_opt!(parse_json_path(path));
+        match target.extract(&[path_expr]) {
+            Some(j) => j,
+            None => return Ok(None),
+        }
+    } else {
+        target.to_owned()
+    };
+
+    Ok(Some(json_contains_one(&target, candidate) as i64))
+}
+
+/// MySQL JSON containment: scalars are contained iff equal; an array
+/// contains `candidate` if `candidate` is one of its elements, or, when
+/// `candidate` is itself an array, every element of `candidate` is
+/// contained; an object contains `candidate` iff every member of
+/// `candidate` exists in `target` with a contained value.
+fn json_contains_one(target: &Json, candidate: &Json) -> bool {
+    match (target, candidate) {
+        (Json::Object(t), Json::Object(c)) => c
+            .iter()
+            .all(|(k, v)| t.get(k).map_or(false, |tv| json_contains_one(tv, v))),
+        (Json::Array(t), Json::Array(c)) => {
+            c.iter().all(|cv| t.iter().any(|tv| json_contains_one(tv, cv)))
+        }
+        (Json::Array(t), _) => t.iter().any(|tv| json_contains_one(tv, candidate)),
+        (t, c) => t == c,
+    }
+}
+
+// Args should be like `(&Option<Json>, &Option<Bytes>, &[&Option<Bytes>])`.
+fn json_contains_path_validator(expr: &tipb::Expr) -> Result<()> {
+    let children = expr.get_children();
+    assert!(children.len() >= 3);
+    super::function::validate_expr_return_type(&children[0], EvalType::Json)?;
+    for child in &children[1..] {
+        super::function::validate_expr_return_type(child, EvalType::Bytes)?;
+    }
+    Ok(())
+}
+
+#[rpn_fn(raw_varg, min_args = 3, extra_validator = json_contains_path_validator)]
+#[inline]
+fn json_contains_path(args: &[ScalarValueRef]) -> Result<Option<Int>> {
+    assert!(args.len() >= 3);
+    let j: &Option<Json> = args[0].as_ref();
+    let j = match j.as_ref() {
+        None => return Ok(None),
+        Some(j) => j,
+    };
+
+    let one_or_all: &Option<Bytes> = args[1].as_ref();
+    let find_all = match one_or_all.as_ref() {
+        None => return Ok(None),
+        Some(b) => {
+            match std::str::from_utf8(b)
+                .map_err(crate::codec::Error::from)?
+                .to_lowercase()
+                .as_str()
+            {
+                "one" => false,
+                "all" => true,
+                _ => {
+                    return Err(other_err!(
+                        "The oneOrAll argument to json_contains_path may take the values 'one' or 'all'"
+                    ))
+                }
+            }
+        }
+    };
+
+    let mut any_exists = false;
+    let mut all_exist = true;
+    for path in &args[2..] {
+        let path: &Option<Bytes> = path.as_ref();
+        let path_expr = try_opt!(parse_json_path(path));
+        let exists = j.extract(&[path_expr]).is_some();
+        any_exists |= exists;
+        all_exist &= exists;
+    }
+
+    Ok(Some(if find_all { all_exist } else { any_exists } as i64))
+}
+
+// Args should be like `(&Option<Json>, &Option<Bytes>, &Option<Bytes>[, &Option<Bytes>, &[&Option<Bytes>]])`.
+fn json_search_validator(expr: &tipb::Expr) -> Result<()> {
+    let children = expr.get_children();
+    assert!(children.len() >= 3);
+    super::function::validate_expr_return_type(&children[0], EvalType::Json)?;
+    for child in &children[1..] {
+        super::function::validate_expr_return_type(child, EvalType::Bytes)?;
+    }
+    Ok(())
+}
+
+#[rpn_fn(raw_varg, min_args = 3, extra_validator = json_search_validator)]
+#[inline]
+fn json_search(args: &[ScalarValueRef]) -> Result<Option<Json>> {
+    assert!(args.len() >= 3);
+    let j: &Option<Json> = args[0].as_ref();
+    let j = match j.as_ref() {
+        None => return Ok(None),
+        Some(j) => j.to_owned(),
+    };
+
+    let one_or_all: &Option<Bytes> = args[1].as_ref();
+    let find_all = match one_or_all.as_ref() {
+        None => return Ok(None),
+        Some(b) => {
+            match std::str::from_utf8(b)
+                .map_err(crate::codec::Error::from)?
+                .to_lowercase()
+                .as_str()
+            {
+                "one" => false,
+                "all" => true,
+                _ => {
+                    return Err(other_err!(
+                        "The oneOrAll argument to json_search may take the values 'one' or 'all'"
+                    ))
+                }
+            }
+        }
+    };
+
+    let search_str: &Option<Bytes> = args[2].as_ref();
+    let search_str = match search_str.as_ref() {
+        None => return Ok(None),
+        Some(s) => String::from_utf8(s.to_owned()).map_err(crate::codec::Error::from)?,
+    };
+
+    let escape = if args.len() > 3 {
+        let escape_arg: &Option<Bytes> = args[3].as_ref();
+        match escape_arg.as_ref() {
+            // MySQL's default escape character is `\`, not "no escaping",
+            // and a SQL NULL escape argument falls back to that same
+            // default rather than disabling escaping outright; only an
+            // explicit empty string does that.
+            None => Some(b'\\'),
+            Some(s) => s.first().copied(),
+        }
+    } else {
+        Some(b'\\')
+    };
+
+    let mut matches = Vec::new();
+    if args.len() > 4 {
+        // Search each requested path's subtree separately (rather than via
+        // `j.extract(&path_expr_list)`, which would merge every subtree into
+        // one `Json::Array` and lose the per-path location), and report
+        // matches as absolute paths rooted at the document, not at the
+        // subtree.
+        let mut seen = std::collections::HashSet::new();
+        for path in &args[4..] {
+            let path: &Option<Bytes> = path.as_ref();
+            let path_str = match path.as_ref() {
+                None => return Ok(None),
+                Some(p) => String::from_utf8(p.to_owned()).map_err(crate::codec::Error::from)?,
+            };
+            let path_expr = try_opt!(parse_json_path(path));
+            let subtree = match j.extract(&[path_expr]) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            let mut sub_matches = Vec::new();
+            collect_json_search_matches(
+                &subtree,
+                path_str,
+                &search_str,
+                escape,
+                find_all,
+                &mut sub_matches,
+            );
+            for m in sub_matches {
+                if seen.insert(m.clone()) {
+                    matches.push(m);
+                }
+            }
+            if !find_all && !matches.is_empty() {
+                break;
+            }
+        }
+    } else {
+        collect_json_search_matches(&j, "$".to_owned(), &search_str, escape, find_all, &mut matches);
+    }
+
+    if matches.is_empty() {
+        return Ok(None);
+    }
+    if matches.len() == 1 {
+        return Ok(Some(Json::String(matches.remove(0))));
+    }
+    Ok(Some(Json::Array(matches.into_iter().map(Json::String).collect())))
+}
+
+/// Walks `json` depth-first, recording the path to every string member that
+/// matches `search_str` under MySQL `LIKE` semantics. Stops at the first hit
+/// once `find_all` is false.
+fn collect_json_search_matches(
+    json: &Json,
+    path: String,
+    search_str: &str,
+    escape: Option<u8>,
+    find_all: bool,
+    matches: &mut Vec<String>,
+) {
+    if !find_all && !matches.is_empty() {
+        return;
+    }
+    match json {
+        Json::String(s) => {
+            if like_match(s, search_str, escape) {
+                matches.push(path);
+            }
+        }
+        Json::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                collect_json_search_matches(
+                    item,
+                    format!("{}[{}]", path, i),
+                    search_str,
+                    escape,
+                    find_all,
+                    matches,
+                );
+                if !find_all && !matches.is_empty() {
+                    return;
+                }
+            }
+        }
+        Json::Object(map) => {
+            for (k, v) in map {
+                collect_json_search_matches(
+                    v,
+                    format!("{}.{}", path, k),
+                    search_str,
+                    escape,
+                    find_all,
+                    matches,
+                );
+                if !find_all && !matches.is_empty() {
+                    return;
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Matches `text` against a MySQL `LIKE`-style `pattern`: `%` matches any
+/// sequence of characters, `_` matches exactly one, and the byte in `escape`
+/// (if any) makes the following pattern character literal.
+fn like_match(text: &str, pattern: &str, escape: Option<u8>) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let escape = escape.map(|e| e as char);
+    like_match_impl(&text, &pattern, escape)
+}
+
+fn like_match_impl(text: &[char], pattern: &[char], escape: Option<char>) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((&p, rest)) if Some(p) == escape && !rest.is_empty() => match text.split_first() {
+            Some((&t, trest)) if t == rest[0] => like_match_impl(trest, &rest[1..], escape),
+            _ => false,
+        },
+        Some((&'%', rest)) => {
+            like_match_impl(text, rest, escape)
+                || (!text.is_empty() && like_match_impl(&text[1..], pattern, escape))
+        }
+        Some((&'_', rest)) => !text.is_empty() && like_match_impl(&text[1..], rest, escape),
+        Some((&p, rest)) => match text.split_first() {
+            Some((&t, trest)) if t == p => like_match_impl(trest, rest, escape),
+            _ => false,
+        },
+    }
+}
+
 fn parse_json_path_list(args: &[ScalarValueRef]) -> Result<Option<Vec<PathExpression>>> {
     let mut path_expr_list = Vec::with_capacity(args.len());
     for arg in args {
@@ -396,6 +944,90 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_json_array_append() {
+        let cases: Vec<(_, Vec<ScalarValue>, _)> = vec![
+            (
+                ScalarFuncSig::JsonArrayAppendSig,
+                vec![
+                    Some(Json::from_str(r#"["a", "b"]"#).unwrap()).into(),
+                    Some(b"$".to_vec()).into(),
+                    Some(Json::from_str(r#""c""#).unwrap()).into(),
+                ],
+                Some(r#"["a", "b", "c"]"#.parse().unwrap()),
+            ),
+            (
+                // Appending into a scalar wraps it into a one-element array first.
+                ScalarFuncSig::JsonArrayAppendSig,
+                vec![
+                    Some(Json::I64(1)).into(),
+                    Some(b"$".to_vec()).into(),
+                    Some(Json::I64(2)).into(),
+                ],
+                Some(r#"[1, 2]"#.parse().unwrap()),
+            ),
+            (
+                // A non-existent path is a no-op.
+                ScalarFuncSig::JsonArrayAppendSig,
+                vec![
+                    Some(Json::from_str(r#"{"a":1}"#).unwrap()).into(),
+                    Some(b"$.b".to_vec()).into(),
+                    Some(Json::I64(2)).into(),
+                ],
+                Some(r#"{"a":1}"#.parse().unwrap()),
+            ),
+        ];
+        for (sig, args, expect_output) in cases {
+            let output: Option<Json> = RpnFnScalarEvaluator::new()
+                .push_params(args.clone())
+                .evaluate(sig)
+                .unwrap();
+            assert_eq!(output, expect_output, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_json_array_insert() {
+        let cases: Vec<(_, Vec<ScalarValue>, _)> = vec![
+            (
+                ScalarFuncSig::JsonArrayInsertSig,
+                vec![
+                    Some(Json::from_str(r#"["a", "b"]"#).unwrap()).into(),
+                    Some(b"$[1]".to_vec()).into(),
+                    Some(Json::from_str(r#""x""#).unwrap()).into(),
+                ],
+                Some(r#"["a", "x", "b"]"#.parse().unwrap()),
+            ),
+            (
+                // An out-of-range index appends at the end.
+                ScalarFuncSig::JsonArrayInsertSig,
+                vec![
+                    Some(Json::from_str(r#"["a", "b"]"#).unwrap()).into(),
+                    Some(b"$[10]".to_vec()).into(),
+                    Some(Json::from_str(r#""x""#).unwrap()).into(),
+                ],
+                Some(r#"["a", "b", "x"]"#.parse().unwrap()),
+            ),
+            (
+                // A path not ending in an array index is a no-op.
+                ScalarFuncSig::JsonArrayInsertSig,
+                vec![
+                    Some(Json::from_str(r#"["a", "b"]"#).unwrap()).into(),
+                    Some(b"$".to_vec()).into(),
+                    Some(Json::from_str(r#""x""#).unwrap()).into(),
+                ],
+                Some(r#"["a", "b"]"#.parse().unwrap()),
+            ),
+        ];
+        for (sig, args, expect_output) in cases {
+            let output: Option<Json> = RpnFnScalarEvaluator::new()
+                .push_params(args.clone())
+                .evaluate(sig)
+                .unwrap();
+            assert_eq!(output, expect_output, "{:?}", args);
+        }
+    }
+
     #[test]
     fn test_json_array() {
         let cases = vec![
@@ -459,6 +1091,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_json_merge_patch() {
+        let cases = vec![
+            (vec![None, None], None),
+            (vec![Some("{}"), Some(r#"{"a":1}"#)], Some(r#"{"a":1}"#)),
+            (
+                vec![Some(r#"{"a":"x"}"#), Some(r#"{"a":null}"#)],
+                Some(r#"{}"#),
+            ),
+            (
+                vec![Some(r#"{"a":{"x":1}}"#), Some(r#"{"a":{"y":2}}"#)],
+                Some(r#"{"a":{"x":1,"y":2}}"#),
+            ),
+            (
+                // A scalar/array patch wholly replaces the target, unlike
+                // json_merge's array-concatenation behavior.
+                vec![Some(r#"{"a":1}"#), Some("[1,2]")],
+                Some("[1,2]"),
+            ),
+            (vec![Some(r#"{"a":1}"#), None, Some(r#"{"b":2}"#)], Some(r#"{"b":2}"#)),
+        ];
+
+        for (vargs, expected) in cases {
+            let vargs = vargs
+                .into_iter()
+                .map(|input| input.map(|s| Json::from_str(s).unwrap()))
+                .collect::<Vec<_>>();
+            let expected = expected.map(|s| Json::from_str(s).unwrap());
+
+            let output = RpnFnScalarEvaluator::new()
+                .push_params(vargs.clone())
+                .evaluate(ScalarFuncSig::JsonMergePatchSig)
+                .unwrap();
+            assert_eq!(output, expected, "{:?}", vargs);
+        }
+    }
+
     #[test]
     fn test_json_object() {
         let cases = vec![
@@ -551,6 +1220,89 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_json_keys() {
+        let cases: Vec<(Vec<ScalarValue>, _)> = vec![
+            (vec![Some(Json::from_str("1").unwrap()).into()], None),
+            (
+                vec![Some(Json::from_str(r#"{"a":1,"b":2}"#).unwrap()).into()],
+                Some(r#"["a", "b"]"#),
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"{"a":{"c":1},"b":2}"#).unwrap()).into(),
+                    Some(b"$.a".to_vec()).into(),
+                ],
+                Some(r#"["c"]"#),
+            ),
+        ];
+
+        for (vargs, expected) in cases {
+            let expected = expected.map(|s| Json::from_str(s).unwrap());
+
+            let output = RpnFnScalarEvaluator::new()
+                .push_params(vargs.clone())
+                .evaluate(ScalarFuncSig::JsonKeysSig)
+                .unwrap();
+            assert_eq!(output, expected, "{:?}", vargs);
+        }
+    }
+
+    #[test]
+    fn test_json_pretty() {
+        let cases = vec![
+            (Some(r#"1"#), Some("1")),
+            (Some(r#"{}"#), Some("{}")),
+            (Some(r#"[]"#), Some("[]")),
+            (
+                Some(r#"{"a":1,"b":[2,3]}"#),
+                Some(
+                    "{\n  \"a\": 1,\n  \"b\": [\n    2,\n    3\n  ]\n}",
+                ),
+            ),
+            // Control characters must be escaped as valid JSON (`\u0001`),
+            // not as Rust's `Debug` syntax (`\u{1}`).
+            (
+                Some("{\"a\":\"\\u0001\"}"),
+                Some("{\n  \"a\": \"\\u0001\"\n}"),
+            ),
+        ];
+
+        for (arg, expect_output) in cases {
+            let arg = arg.map(|input| Json::from_str(input).unwrap());
+            let expect_output = expect_output.map(Bytes::from);
+
+            let output = RpnFnScalarEvaluator::new()
+                .push_param(arg.clone())
+                .evaluate(ScalarFuncSig::JsonPrettySig)
+                .unwrap();
+            assert_eq!(output, expect_output, "{:?}", arg);
+        }
+    }
+
+    #[test]
+    fn test_json_quote() {
+        let cases = vec![
+            (None, None),
+            (Some(r#"a"#), Some(r#""a""#)),
+            (Some(r#"3"#), Some(r#""3""#)),
+            (Some("\"a\""), Some(r#""\"a\"""#)),
+            (Some("a\nb"), Some(r#""a\nb""#)),
+            (Some("a\\b"), Some(r#""a\\b""#)),
+        ];
+
+        for (arg, expect_output) in cases {
+            let arg = arg.map(Bytes::from);
+            let expect_output = expect_output.map(Bytes::from);
+
+            let output = RpnFnScalarEvaluator::new()
+                .push_param(arg.clone())
+                .evaluate(ScalarFuncSig::JsonQuoteSig)
+                .unwrap();
+            assert_eq!(output, expect_output, "{:?}", arg);
+        }
+    }
+
     #[test]
     fn test_json_extract() {
         let cases: Vec<(Vec<ScalarValue>, _)> = vec![
@@ -619,6 +1371,235 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_json_contains() {
+        let cases: Vec<(Vec<ScalarValue>, _)> = vec![
+            (
+                vec![None::<Json>.into(), Some(Json::from_str("1").unwrap()).into()],
+                None,
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"{"a":1,"b":2}"#).unwrap()).into(),
+                    Some(Json::from_str(r#"{"a":1}"#).unwrap()).into(),
+                ],
+                Some(1),
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"{"a":1,"b":2}"#).unwrap()).into(),
+                    Some(Json::from_str(r#"{"a":2}"#).unwrap()).into(),
+                ],
+                Some(0),
+            ),
+            (
+                vec![
+                    Some(Json::from_str("[1,2,[3,4]]").unwrap()).into(),
+                    Some(Json::from_str("[3,4]").unwrap()).into(),
+                ],
+                Some(1),
+            ),
+            (
+                vec![
+                    Some(Json::from_str("[1,2,3]").unwrap()).into(),
+                    Some(Json::from_str("2").unwrap()).into(),
+                ],
+                Some(1),
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"{"a":{"b":1}}"#).unwrap()).into(),
+                    Some(Json::from_str("1").unwrap()).into(),
+                    Some(b"$.a.b".to_vec()).into(),
+                ],
+                Some(1),
+            ),
+        ];
+
+        for (vargs, expected) in cases {
+            let output = RpnFnScalarEvaluator::new()
+                .push_params(vargs.clone())
+                .evaluate(ScalarFuncSig::JsonContainsSig)
+                .unwrap();
+            assert_eq!(output, expected, "{:?}", vargs);
+        }
+    }
+
+    #[test]
+    fn test_json_contains_path() {
+        let cases: Vec<(Vec<ScalarValue>, _)> = vec![
+            (
+                vec![
+                    Some(Json::from_str(r#"{"a":1,"b":2}"#).unwrap()).into(),
+                    Some(b"one".to_vec()).into(),
+                    Some(b"$.a".to_vec()).into(),
+                    Some(b"$.c".to_vec()).into(),
+                ],
+                Some(1),
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"{"a":1,"b":2}"#).unwrap()).into(),
+                    Some(b"all".to_vec()).into(),
+                    Some(b"$.a".to_vec()).into(),
+                    Some(b"$.c".to_vec()).into(),
+                ],
+                Some(0),
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"{"a":1,"b":2}"#).unwrap()).into(),
+                    Some(b"all".to_vec()).into(),
+                    Some(b"$.a".to_vec()).into(),
+                    Some(b"$.b".to_vec()).into(),
+                ],
+                Some(1),
+            ),
+        ];
+
+        for (vargs, expected) in cases {
+            let output = RpnFnScalarEvaluator::new()
+                .push_params(vargs.clone())
+                .evaluate(ScalarFuncSig::JsonContainsPathSig)
+                .unwrap();
+            assert_eq!(output, expected, "{:?}", vargs);
+        }
+    }
+
+    #[test]
+    fn test_json_search() {
+        let cases: Vec<(Vec<ScalarValue>, _)> = vec![
+            (
+                vec![
+                    None::<Json>.into(),
+                    Some(b"one".to_vec()).into(),
+                    Some(b"abc".to_vec()).into(),
+                ],
+                None,
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"["abc", [3, "abc"], "bcd"]"#).unwrap()).into(),
+                    Some(b"one".to_vec()).into(),
+                    Some(b"abc".to_vec()).into(),
+                ],
+                Some(r#""$[0]""#),
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"["abc", [3, "abc"], "bcd"]"#).unwrap()).into(),
+                    Some(b"all".to_vec()).into(),
+                    Some(b"abc".to_vec()).into(),
+                ],
+                Some(r#"["$[0]", "$[1][1]"]"#),
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"{"a": "banana", "b": {"c": "orange"}}"#).unwrap())
+                        .into(),
+                    Some(b"one".to_vec()).into(),
+                    Some(b"or%".to_vec()).into(),
+                ],
+                Some(r#""$.b.c""#),
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"["abc", "abd"]"#).unwrap()).into(),
+                    Some(b"all".to_vec()).into(),
+                    Some(b"ab_".to_vec()).into(),
+                ],
+                Some(r#"["$[0]", "$[1]"]"#),
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"["abc", "xyz"]"#).unwrap()).into(),
+                    Some(b"one".to_vec()).into(),
+                    Some(b"abc".to_vec()).into(),
+                ],
+                Some(r#""$[0]""#),
+            ),
+        ];
+
+        for (vargs, expected) in cases {
+            let expected = expected.map(|s| Json::from_str(s).unwrap());
+
+            let output = RpnFnScalarEvaluator::new()
+                .push_params(vargs.clone())
+                .evaluate(ScalarFuncSig::JsonSearchSig)
+                .unwrap();
+            assert_eq!(output, expected, "{:?}", vargs);
+        }
+
+        let output: Result<Option<Json>> = RpnFnScalarEvaluator::new()
+            .push_params(vec![
+                Some(Json::from_str(r#"["abc"]"#).unwrap()).into(),
+                Some(b"bogus".to_vec()).into(),
+                Some(b"abc".to_vec()).into(),
+            ])
+            .evaluate(ScalarFuncSig::JsonSearchSig);
+        assert!(output.is_err());
+    }
+
+    #[test]
+    fn test_json_search_with_paths() {
+        // Matches reported under a trailing path must be absolute (rooted at
+        // the document), not relative to the extracted subtree.
+        let cases: Vec<(Vec<ScalarValue>, _)> = vec![
+            (
+                vec![
+                    Some(Json::from_str(r#"{"a": {"b": "x"}, "c": {"b": "x"}}"#).unwrap()).into(),
+                    Some(b"all".to_vec()).into(),
+                    Some(b"x".to_vec()).into(),
+                    None::<Bytes>.into(),
+                    Some(b"$.a".to_vec()).into(),
+                ],
+                Some(r#""$.a.b""#),
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"{"a": {"b": "x"}, "c": {"b": "x"}}"#).unwrap()).into(),
+                    Some(b"all".to_vec()).into(),
+                    Some(b"x".to_vec()).into(),
+                    None::<Bytes>.into(),
+                    Some(b"$.a".to_vec()).into(),
+                    Some(b"$.c".to_vec()).into(),
+                ],
+                Some(r#"["$.a.b", "$.c.b"]"#),
+            ),
+            (
+                vec![
+                    Some(Json::from_str(r#"{"a": {"b": "x"}, "c": {"b": "y"}}"#).unwrap()).into(),
+                    Some(b"all".to_vec()).into(),
+                    Some(b"x".to_vec()).into(),
+                    None::<Bytes>.into(),
+                    Some(b"$.c".to_vec()).into(),
+                ],
+                None,
+            ),
+            // No explicit escape argument: `\` is still the escape character,
+            // so `\%` must match a literal `%` rather than being treated as
+            // a literal backslash followed by the `%` wildcard.
+            (
+                vec![
+                    Some(Json::from_str(r#"["100%", "100x"]"#).unwrap()).into(),
+                    Some(b"one".to_vec()).into(),
+                    Some(b"100\\%".to_vec()).into(),
+                ],
+                Some(r#""$[0]""#),
+            ),
+        ];
+
+        for (vargs, expected) in cases {
+            let expected = expected.map(|s| Json::from_str(s).unwrap());
+
+            let output = RpnFnScalarEvaluator::new()
+                .push_params(vargs.clone())
+                .evaluate(ScalarFuncSig::JsonSearchSig)
+                .unwrap();
+            assert_eq!(output, expected, "{:?}", vargs);
+        }
+    }
+
     #[test]
     fn test_json_length() {
         let cases: Vec<(Vec<ScalarValue>, Option<i64>)> = vec![
@@ -16,13 +16,110 @@ use crate::raftstore::coprocessor::CoprocessorHost;
 use crate::server::resolve::StoreAddrResolver;
 use crate::server::{Error, Result};
 use crate::storage::{lock_manager::Lock, txn::ProcessResult, LockMgr, StorageCb};
+#[cfg(feature = "lock-backtrace")]
+use backtrace::Backtrace;
 use pd_client::RpcClient;
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 use tikv_util::security::SecurityManager;
 use tikv_util::worker::FutureWorker;
 
+/// A purely local, advisory wait-for graph `LockManager::wait_for` consults
+/// before involving the remote `Detector`, so obvious cycles are caught
+/// in-process. A miss here is not a correctness problem: the remote
+/// detector still runs and is the source of truth, this is only a
+/// fast-path short-circuit for cycles cheap enough to see locally.
+#[derive(Default)]
+struct LocalWaitForGraph {
+    /// `waiting[start_ts]` is the set of `lock_ts` that `start_ts` is
+    /// currently blocked on.
+    waiting: HashMap<u64, HashSet<u64>>,
+    /// Reverse index of `waiting`: `held_by[lock_ts]` is the set of
+    /// `start_ts` blocked on `lock_ts`. Kept in lockstep with `waiting` so
+    /// a released lock's edges can be found and removed in both
+    /// directions from commit/wake events without scanning the whole
+    /// graph.
+    held_by: HashMap<u64, HashSet<u64>>,
+}
+
+impl LocalWaitForGraph {
+    /// Inserts the edge `start_ts -> lock_ts` and runs an iterative DFS
+    /// from `lock_ts` looking for a path back to `start_ts`. If one
+    /// exists, the edge just inserted closes a cycle; the returned vec is
+    /// the chain of `lock_ts` hops that make it up, for logging.
+    fn insert_and_check_cycle(&mut self, start_ts: u64, lock_ts: u64) -> Option<Vec<u64>> {
+        self.waiting.entry(start_ts).or_default().insert(lock_ts);
+        self.held_by.entry(lock_ts).or_default().insert(start_ts);
+
+        let mut stack = vec![lock_ts];
+        // Nodes popped with all their children pushed are done (BLACK);
+        // nodes never popped are GREY (on the current DFS frontier) or
+        // unvisited. Either way, once a node is marked here it never needs
+        // to be explored again: if it could reach `start_ts` that would
+        // already have been discovered.
+        let mut visited: HashSet<u64> = HashSet::default();
+        let mut parent: HashMap<u64, u64> = HashMap::default();
+        while let Some(node) = stack.pop() {
+            if !visited.insert(node) {
+                continue;
+            }
+            if node == start_ts {
+                let mut cycle = vec![node];
+                let mut cur = node;
+                while let Some(&p) = parent.get(&cur) {
+                    cycle.push(p);
+                    cur = p;
+                }
+                cycle.reverse();
+                return Some(cycle);
+            }
+            if let Some(next_hops) = self.waiting.get(&node) {
+                for &next in next_hops {
+                    if !visited.contains(&next) {
+                        parent.entry(next).or_insert(node);
+                        stack.push(next);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Removes every edge that `start_ts` owns, i.e. after its wait has
+    /// been resolved one way or another (woken, cancelled, or failed as a
+    /// deadlock victim) so the graph doesn't grow unbounded.
+    fn remove_waiter(&mut self, start_ts: u64) {
+        if let Some(lock_tss) = self.waiting.remove(&start_ts) {
+            for lock_ts in lock_tss {
+                if let Some(waiters) = self.held_by.get_mut(&lock_ts) {
+                    waiters.remove(&start_ts);
+                    if waiters.is_empty() {
+                        self.held_by.remove(&lock_ts);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes every edge waiting on `lock_ts`, i.e. once its holder
+    /// commits or is cleaned up (`wake_up`) and the lock no longer exists
+    /// to be a link in any cycle.
+    fn remove_lock(&mut self, lock_ts: u64) {
+        if let Some(waiters) = self.held_by.remove(&lock_ts) {
+            for start_ts in waiters {
+                if let Some(locks) = self.waiting.get_mut(&start_ts) {
+                    locks.remove(&lock_ts);
+                    if locks.is_empty() {
+                        self.waiting.remove(&start_ts);
+                    }
+                }
+            }
+        }
+    }
+}
+
 /// `LockManager` has two components working in two threads:
 ///   * One is the `WaiterManager` which manages transactions waiting for locks.
 ///   * The other one is the `Detector` which detects deadlocks between transactions.
@@ -34,6 +131,15 @@ pub struct LockManager {
     detector_scheduler: DetectorScheduler,
 
     waiter_count: Arc<AtomicUsize>,
+
+    wait_for_graph: Arc<Mutex<LocalWaitForGraph>>,
+
+    /// Backtraces captured at the moment each waiting transaction entered
+    /// `wait_for`, keyed by `start_ts`. Only maintained when the
+    /// `lock-backtrace` feature is enabled, since capturing a backtrace on
+    /// every lock wait is too expensive for always-on production use.
+    #[cfg(feature = "lock-backtrace")]
+    waiter_backtraces: Arc<Mutex<HashMap<u64, Backtrace>>>,
 }
 
 impl Clone for LockManager {
@@ -44,6 +150,9 @@ impl Clone for LockManager {
             waiter_mgr_scheduler: self.waiter_mgr_scheduler.clone(),
             detector_scheduler: self.detector_scheduler.clone(),
             waiter_count: self.waiter_count.clone(),
+            wait_for_graph: self.wait_for_graph.clone(),
+            #[cfg(feature = "lock-backtrace")]
+            waiter_backtraces: self.waiter_backtraces.clone(),
         }
     }
 }
@@ -59,6 +168,9 @@ impl LockManager {
             detector_scheduler: DetectorScheduler::new(detector_worker.scheduler()),
             detector_worker: Some(detector_worker),
             waiter_count: Arc::new(AtomicUsize::new(0)),
+            wait_for_graph: Arc::new(Mutex::new(LocalWaitForGraph::default())),
+            #[cfg(feature = "lock-backtrace")]
+            waiter_backtraces: Arc::new(Mutex::new(HashMap::default())),
         }
     }
 
@@ -160,6 +272,25 @@ impl LockManager {
             self.detector_scheduler.clone(),
         )
     }
+
+    /// Drops `start_ts`'s entry from the local wait-for graph.
+    ///
+    /// `wake_up` already does this for the lock side: once a lock is
+    /// released (or cleaned up) every edge waiting on it is removed. But a
+    /// waiter can also be resolved without a matching `wake_up` at all,
+    /// namely when `WaiterManager`'s own wait-timeout elapses and it
+    /// settles the waiter's callback itself (e.g. with a retryable
+    /// `KeyIsLocked`) rather than being woken by the lock holder. Without
+    /// this entry point that waiter's edge would never be removed, growing
+    /// `wait_for_graph` without bound on a busy cluster. `WaiterManager`
+    /// (`waiter_manager.rs`, not present in this snapshot) is expected to
+    /// call this once it resolves a waiter through its own timeout path,
+    /// the same way it already calls back into the scheduler to do so.
+    pub fn clean_up_wait_for(&self, start_ts: u64) {
+        self.wait_for_graph.lock().unwrap().remove_waiter(start_ts);
+        #[cfg(feature = "lock-backtrace")]
+        self.waiter_backtraces.lock().unwrap().remove(&start_ts);
+    }
 }
 
 impl LockMgr for LockManager {
@@ -174,14 +305,65 @@ impl LockMgr for LockManager {
         // Increase `waiter_count` here to prevent there is an on-the-fly WaitFor msg
         // but the waiter_mgr haven't processed it, subsequent WakeUp msgs may be lost.
         self.waiter_count.fetch_add(1, Ordering::SeqCst);
+
+        #[cfg(feature = "lock-backtrace")]
+        self.waiter_backtraces
+            .lock()
+            .unwrap()
+            .insert(start_ts, Backtrace::new());
+
         self.waiter_mgr_scheduler.wait_for(start_ts, cb, pr, lock);
 
+        // Advisory local cycle pre-check: insert the wait-for edge and look
+        // for a path back to `start_ts` without a round trip to the remote
+        // `Detector`. The graph mutex is released before touching the
+        // waiter manager again, so it's never held while a waiter callback
+        // could run (deadlock-by-reentrancy).
+        let cycle = self
+            .wait_for_graph
+            .lock()
+            .unwrap()
+            .insert_and_check_cycle(start_ts, lock.ts);
+        if let Some(cycle) = cycle {
+            #[cfg(feature = "lock-backtrace")]
+            {
+                let backtraces = self.waiter_backtraces.lock().unwrap();
+                let participants: Vec<_> = cycle
+                    .iter()
+                    .filter_map(|ts| backtraces.get(ts).map(|bt| (*ts, bt)))
+                    .collect();
+                warn!(
+                    "local wait-for graph found a cycle, failing as deadlock without a remote detector round trip";
+                    "start_ts" => start_ts, "lock_ts" => lock.ts, "cycle" => ?cycle,
+                    "backtraces" => ?participants,
+                );
+            }
+            #[cfg(not(feature = "lock-backtrace"))]
+            warn!(
+                "local wait-for graph found a cycle, failing as deadlock without a remote detector round trip";
+                "start_ts" => start_ts, "lock_ts" => lock.ts, "cycle" => ?cycle,
+            );
+            self.wait_for_graph.lock().unwrap().remove_waiter(start_ts);
+            #[cfg(feature = "lock-backtrace")]
+            self.waiter_backtraces.lock().unwrap().remove(&start_ts);
+            self.waiter_mgr_scheduler.deadlock(start_ts, lock, lock.hash);
+            return;
+        }
+
         // If it is the first lock the transaction waits for, it won't cause deadlock.
         if !is_first_lock {
             self.detector_scheduler.detect(start_ts, lock);
         }
     }
 
+    // A client disconnect or statement timeout that resolves one specific
+    // waiter without a matching `wake_up` is handled on the waiter side by
+    // `WaiterManager` (`waiter_manager.rs`, not present in this snapshot) —
+    // `LockManager` here only forwards `start_ts`/`Lock` onto its scheduler
+    // and has no storage for individual waiter entries. Whichever path
+    // settles such a waiter should call `clean_up_wait_for(start_ts)` the
+    // same way `wake_up` below calls `remove_lock`, so the local wait-for
+    // graph doesn't keep an edge for a waiter that no longer exists.
     fn wake_up(
         &self,
         lock_ts: u64,
@@ -195,6 +377,9 @@ impl LockMgr for LockManager {
             self.waiter_mgr_scheduler
                 .wake_up(lock_ts, hashes, commit_ts);
         }
+        // The lock has been released (or is being cleaned up): any local
+        // wait-for edges pointing at it are stale, so drop them here too.
+        self.wait_for_graph.lock().unwrap().remove_lock(lock_ts);
         // If these locks belong to a pessimistic transaction, clean up its wait-for entries
         // in the deadlock detector.
         //
@@ -238,6 +423,35 @@ mod tests {
         lock_mgr.stop_waiter_manager();
     }
 
+    #[test]
+    fn test_clean_up_wait_for_removes_dangling_edge() {
+        // Simulates a waiter resolved by the waiter manager's own
+        // wait-timeout: no `wake_up` for the lock it was waiting on ever
+        // arrives, so `clean_up_wait_for` is the only thing that can drop
+        // its edge.
+        let lock_mgr = LockManager::new();
+        let (start_ts, lock_ts, hash) = (10, 20, 1);
+        lock_mgr.wait_for(
+            start_ts,
+            StorageCb::Boolean(Box::new(|_| ())),
+            ProcessResult::Res,
+            Lock { ts: lock_ts, hash },
+            true,
+        );
+        assert!(lock_mgr
+            .wait_for_graph
+            .lock()
+            .unwrap()
+            .waiting
+            .contains_key(&start_ts));
+
+        lock_mgr.clean_up_wait_for(start_ts);
+
+        let graph = lock_mgr.wait_for_graph.lock().unwrap();
+        assert!(!graph.waiting.contains_key(&start_ts));
+        assert!(!graph.held_by.contains_key(&lock_ts));
+    }
+
     #[bench]
     fn bench_lock_mgr_clone(b: &mut test::Bencher) {
         let lock_mgr = LockManager::new();
@@ -245,4 +459,50 @@ mod tests {
             test::black_box(lock_mgr.clone());
         })
     }
+
+    #[test]
+    fn test_local_wait_for_graph_detects_cycle() {
+        let mut graph = LocalWaitForGraph::default();
+        // 1 -> 2 -> 3, no cycle yet.
+        assert!(graph.insert_and_check_cycle(1, 2).is_none());
+        assert!(graph.insert_and_check_cycle(2, 3).is_none());
+        // 3 -> 1 closes the cycle 1 -> 2 -> 3 -> 1; the returned chain is
+        // the path from the newly-waited-on lock back to the new waiter.
+        let cycle = graph.insert_and_check_cycle(3, 1);
+        assert_eq!(cycle, Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_local_wait_for_graph_garbage_collects_edges() {
+        // Tearing down the waiter's own entry removes its outgoing edge
+        // and the matching reverse entry.
+        let mut graph = LocalWaitForGraph::default();
+        assert!(graph.insert_and_check_cycle(10, 20).is_none());
+        graph.remove_waiter(10);
+        assert!(graph.waiting.get(&10).is_none());
+        assert!(!graph.held_by.contains_key(&20));
+
+        // Releasing a lock removes every edge waiting on it, including
+        // dropping the waiter entirely once it has no locks left.
+        let mut graph = LocalWaitForGraph::default();
+        assert!(graph.insert_and_check_cycle(10, 20).is_none());
+        graph.remove_lock(20);
+        assert!(!graph.held_by.contains_key(&20));
+        assert!(graph.waiting.get(&10).is_none());
+    }
+
+    #[cfg(feature = "lock-backtrace")]
+    #[test]
+    fn test_wait_for_captures_backtrace() {
+        let lock_mgr = LockManager::new();
+        let (lock_ts, hash) = (10, 1);
+        lock_mgr.wait_for(
+            20,
+            StorageCb::Boolean(Box::new(|_| ())),
+            ProcessResult::Res,
+            Lock { ts: lock_ts, hash },
+            true,
+        );
+        assert!(lock_mgr.waiter_backtraces.lock().unwrap().contains_key(&20));
+    }
 }